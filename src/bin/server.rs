@@ -0,0 +1,260 @@
+//! HTTP server for playing over the network.
+//!
+//! - `POST /game` creates a game and returns its id and state.
+//! - `POST /game/:id/move` applies a move given in notation, e.g. `{"move": "3@a1"}`.
+//! - `GET /game/:id/best?depth=N` returns the engine's recommended move.
+//!
+//! Games are kept in memory and lost when the server stops.
+
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use goblet::{ApplyError, GameState, MinimaxEngine, Player, Score};
+use serde::{Deserialize, Serialize};
+
+#[derive(Default)]
+struct Games {
+    next_id: u64,
+    games: HashMap<u64, GameState>,
+}
+
+type SharedGames = Arc<Mutex<Games>>;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, String)>;
+
+#[derive(Serialize)]
+struct StateView {
+    position: String,
+    turn: &'static str,
+    white_reserves: Vec<i32>,
+    black_reserves: Vec<i32>,
+    winner: Option<&'static str>,
+}
+
+impl StateView {
+    fn new(state: &GameState) -> StateView {
+        StateView {
+            position: state.position_string(),
+            turn: player_name(state.turn()),
+            white_reserves: state.reserves(Player::White).to_vec(),
+            black_reserves: state.reserves(Player::Black).to_vec(),
+            winner: state.raw_score().winner().map(player_name),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct GameView {
+    id: u64,
+    state: StateView,
+}
+
+#[derive(Deserialize)]
+struct MoveRequest {
+    #[serde(rename = "move")]
+    game_move: String,
+}
+
+#[derive(Deserialize)]
+struct BestQuery {
+    depth: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct BestView {
+    #[serde(rename = "move")]
+    game_move: Option<String>,
+    /// A number for balanced positions, otherwise `"white"` or `"black"` for a forced win.
+    score: serde_json::Value,
+}
+
+fn player_name(player: Player) -> &'static str {
+    if player == Player::White {
+        "white"
+    } else {
+        "black"
+    }
+}
+
+fn not_found(id: u64) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("no game with id {id}"))
+}
+
+async fn create_game(State(games): State<SharedGames>) -> Json<GameView> {
+    let mut games = games.lock().unwrap();
+    let id = games.next_id;
+    games.next_id += 1;
+    let state = GameState::new();
+    let view = GameView {
+        id,
+        state: StateView::new(&state),
+    };
+    games.games.insert(id, state);
+    Json(view)
+}
+
+async fn play_move(
+    State(games): State<SharedGames>,
+    Path(id): Path<u64>,
+    Json(request): Json<MoveRequest>,
+) -> ApiResult<GameView> {
+    let mut games = games.lock().unwrap();
+    let state = games.games.get_mut(&id).ok_or_else(|| not_found(id))?;
+    if state.raw_score().is_decisive() {
+        return Err((StatusCode::CONFLICT, "the game is over".to_string()));
+    }
+    state
+        .apply_notation(&request.game_move)
+        .map_err(|error| match error {
+            ApplyError::Notation(_) => (StatusCode::BAD_REQUEST, error.to_string()),
+            ApplyError::Illegal(_) => (StatusCode::UNPROCESSABLE_ENTITY, error.to_string()),
+        })?;
+    Ok(Json(GameView {
+        id,
+        state: StateView::new(state),
+    }))
+}
+
+async fn best_move(
+    State(games): State<SharedGames>,
+    Path(id): Path<u64>,
+    Query(query): Query<BestQuery>,
+) -> ApiResult<BestView> {
+    let state = games
+        .lock()
+        .unwrap()
+        .games
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| not_found(id))?;
+    let depth = query.depth.unwrap_or(3).clamp(1, 6);
+    let result =
+        tokio::task::spawn_blocking(move || MinimaxEngine::new(depth).search_root(&state, depth))
+            .await
+            .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, format!("{error}")))?
+            .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+    Ok(Json(BestView {
+        game_move: result.best_move.map(|game_move| game_move.to_string()),
+        score: match result.score {
+            Score::WhiteFavored(_) => "white".into(),
+            Score::BlackFavored(_) => "black".into(),
+            Score::Balanced(score) => score.into(),
+        },
+    }))
+}
+
+fn app() -> Router {
+    Router::new()
+        .route("/game", post(create_game))
+        .route("/game/:id/move", post(play_move))
+        .route("/game/:id/best", get(best_move))
+        .with_state(SharedGames::default())
+}
+
+#[tokio::main]
+async fn main() {
+    let address = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:3000".to_string());
+    let listener = tokio::net::TcpListener::bind(&address).await.unwrap();
+    println!("listening on {address}");
+    axum::serve(listener, app()).await.unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        thread,
+    };
+
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Serve `app()` on a free local port from a background thread.
+    fn spawn_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        listener.set_nonblocking(true).unwrap();
+        thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(async {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, app()).await.unwrap();
+            })
+        });
+        address
+    }
+
+    /// Send one request and return the status code and the JSON body.
+    fn request(address: SocketAddr, method: &str, path: &str, body: Option<&str>) -> (u16, Value) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let body = body.unwrap_or("");
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: {address}\r\nConnection: close\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    #[test]
+    fn plays_a_short_game_against_the_engine() {
+        let address = spawn_server();
+        let (status, created) = request(address, "POST", "/game", None);
+        assert_eq!(status, 200);
+        assert_eq!(created["state"]["turn"], "white");
+        let id = created["id"].as_u64().unwrap();
+
+        let (status, moved) = request(
+            address,
+            "POST",
+            &format!("/game/{id}/move"),
+            Some(r#"{"move": "3@b2"}"#),
+        );
+        assert_eq!(status, 200);
+        assert_eq!(moved["state"]["turn"], "black");
+        assert_eq!(
+            moved["state"]["white_reserves"],
+            serde_json::json!([3, 3, 3, 2])
+        );
+
+        let (status, best) = request(address, "GET", &format!("/game/{id}/best?depth=2"), None);
+        assert_eq!(status, 200);
+        let reply = best["move"].as_str().unwrap();
+        let (status, replied) = request(
+            address,
+            "POST",
+            &format!("/game/{id}/move"),
+            Some(&format!(r#"{{"move": "{reply}"}}"#)),
+        );
+        assert_eq!(status, 200);
+        assert_eq!(replied["state"]["turn"], "white");
+
+        let (status, _) = request(
+            address,
+            "POST",
+            &format!("/game/{id}/move"),
+            Some(r#"{"move": "3@b2"}"#),
+        );
+        assert_eq!(status, 422);
+        let (status, _) = request(address, "GET", "/game/99/best", None);
+        assert_eq!(status, 404);
+    }
+}
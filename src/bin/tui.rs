@@ -0,0 +1,302 @@
+//! Full-screen terminal UI for playing White against an engine.
+//!
+//! Arrow keys move the cursor. Enter or space picks up the top piece under the
+//! cursor, or drops the held piece there; `0` to `3` pick up a piece of that size
+//! from the reserve. Escape puts the held piece back, `u` takes back your last
+//! move and the engine's reply, and `q` quits.
+
+use std::{
+    env,
+    io::{self, Write},
+    panic,
+    time::Duration,
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind},
+    execute, queue,
+    terminal::{self, ClearType},
+};
+use goblet::{
+    transposition::ReplacementPolicy, Color, Engine, EngineConfig, GameState, MinimaxEngine,
+    PendingMove, Player, Position, Score, Source, BOARD_COLS, BOARD_ROWS, NUM_SIZES,
+};
+
+/// Width of the evaluation bar in characters.
+const BAR_WIDTH: usize = 32;
+
+/// Puts the terminal into raw mode on an alternate screen, and back again when
+/// dropped, including while unwinding from a panic.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+fn restore_terminal() {
+    let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+}
+
+struct App {
+    state: GameState,
+    engine: MinimaxEngine,
+    /// The engine's value of the game from its last search, until a take-back
+    /// makes it stale.
+    eval: Option<Score>,
+    cursor: Position,
+    held: Option<PendingMove>,
+    message: String,
+}
+
+/// A cell as three characters: the top piece's color and size, or a dot when
+/// empty, followed by a `*` if the held piece can be dropped there.
+fn render_cell(state: &GameState, position: Position, held: Option<&PendingMove>) -> String {
+    let piece = match state.top_piece(position) {
+        Some((Color::White, size)) => format!("W{size}"),
+        Some((_, size)) => format!("B{size}"),
+        None => " .".to_string(),
+    };
+    let droppable = held.is_some_and(|held| held.destinations().contains(&position));
+    format!("{piece}{}", if droppable { '*' } else { ' ' })
+}
+
+/// The board with the highest row at the top, framed by column letters and row
+/// numbers. The cell under `cursor` is bracketed.
+fn render_board(state: &GameState, cursor: Position, held: Option<&PendingMove>) -> Vec<String> {
+    let files: String = (0..BOARD_COLS)
+        .map(|col| format!("  {}  ", (b'a' + col as u8) as char))
+        .collect();
+    let mut lines = vec![format!("   {files}")];
+    for row in (0..BOARD_ROWS).rev() {
+        let mut line = format!("{:>2} ", row + 1);
+        for col in 0..BOARD_COLS {
+            let cell = render_cell(state, (row, col), held);
+            if (row, col) == cursor {
+                line.push_str(&format!("[{cell}]"));
+            } else {
+                line.push_str(&format!(" {cell} "));
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn render_reserves(state: &GameState) -> Vec<String> {
+    [(Player::White, "White"), (Player::Black, "Black")]
+        .into_iter()
+        .map(|(player, name)| {
+            let counts: Vec<String> = state
+                .reserves(player)
+                .iter()
+                .enumerate()
+                .map(|(size, count)| format!("{size}:{count}"))
+                .collect();
+            format!("{name} reserve  {}", counts.join("  "))
+        })
+        .collect()
+}
+
+/// A bar that fills towards the right as White's position improves, saturating at
+/// `±scale`. Forced wins fill or empty it completely.
+fn eval_bar(score: Score, scale: i32) -> String {
+    let fraction = (score.to_eval_units(scale) + 1.0) / 2.0;
+    let filled = (fraction * BAR_WIDTH as f32) as usize;
+    format!(
+        "Black [{}{}] White",
+        "#".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled)
+    )
+}
+
+fn render(app: &App) -> Vec<String> {
+    let mut lines = render_board(&app.state, app.cursor, app.held.as_ref());
+    lines.push(String::new());
+    lines.extend(render_reserves(&app.state));
+    lines.push(String::new());
+    lines.push(match app.eval {
+        Some(score) => eval_bar(score, 20),
+        None => "The engine evaluates after its next move.".to_string(),
+    });
+    lines.push(String::new());
+    lines.push(app.message.clone());
+    lines
+}
+
+fn draw(app: &App) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    queue!(stdout, terminal::Clear(ClearType::All))?;
+    for (index, line) in render(app).iter().enumerate() {
+        queue!(stdout, cursor::MoveTo(0, index as u16))?;
+        write!(stdout, "{line}")?;
+    }
+    stdout.flush()
+}
+
+fn is_over(state: &GameState) -> bool {
+    state.raw_score().is_decisive() || state.is_dead_draw()
+}
+
+impl App {
+    fn new(engine: MinimaxEngine) -> App {
+        App {
+            state: GameState::new(),
+            engine,
+            eval: None,
+            cursor: (0, 0),
+            held: None,
+            message:
+                "You are White. Arrows move, enter picks up and drops, 0-3 take from the reserve."
+                    .to_string(),
+        }
+    }
+
+    fn lift(&mut self, source: Source) {
+        self.held = PendingMove::lift(&self.state, source);
+        if self.held.is_none() {
+            self.message = "that piece can't be played".to_string();
+        }
+    }
+
+    fn select(&mut self) {
+        let Some(held) = self.held.take() else {
+            self.lift(Source::Board(self.cursor));
+            return;
+        };
+        let Some(game_move) = held.drop_at(self.cursor) else {
+            self.message = "the held piece can't go there".to_string();
+            self.held = Some(held);
+            return;
+        };
+        self.state.make_move(game_move);
+        self.message = format!("you played {game_move}");
+        if is_over(&self.state) {
+            return;
+        }
+        let result = self.engine.search(&self.state).ok();
+        self.eval = result.as_ref().map(|result| result.score);
+        match result.and_then(|result| result.best_move) {
+            Some(reply) => {
+                self.state.make_move(reply);
+                self.message = format!(
+                    "you played {game_move}, {} replied {reply}",
+                    self.engine.name()
+                );
+            }
+            None => self.message = format!("{} has no moves", self.engine.name()),
+        }
+    }
+
+    /// Undo moves until it is White's turn again.
+    fn take_back(&mut self) {
+        self.held = None;
+        self.eval = None;
+        while self.state.undo_last().is_some() {
+            if self.state.turn() == Player::White {
+                self.message = "took back your last move".to_string();
+                return;
+            }
+        }
+        self.message = "nothing to take back".to_string();
+    }
+
+    fn handle(&mut self, code: KeyCode) {
+        let (row, col) = self.cursor;
+        match code {
+            KeyCode::Up => self.cursor = ((row + 1).min(BOARD_ROWS - 1), col),
+            KeyCode::Down => self.cursor = (row.saturating_sub(1), col),
+            KeyCode::Left => self.cursor = (row, col.saturating_sub(1)),
+            KeyCode::Right => self.cursor = (row, (col + 1).min(BOARD_COLS - 1)),
+            KeyCode::Esc => self.held = None,
+            KeyCode::Char('u') => self.take_back(),
+            KeyCode::Enter | KeyCode::Char(' ') if !is_over(&self.state) => self.select(),
+            KeyCode::Char(digit) if !is_over(&self.state) => {
+                if let Some(size) = digit
+                    .to_digit(10)
+                    .filter(|&size| (size as usize) < NUM_SIZES)
+                {
+                    self.lift(Source::Reserve(size as usize));
+                }
+            }
+            _ => (),
+        }
+        match self.state.raw_score() {
+            Score::WhiteFavored(_) => self.message = "White wins! Press q to quit.".to_string(),
+            Score::BlackFavored(_) => self.message = "Black wins! Press q to quit.".to_string(),
+            Score::Balanced(_) if self.state.is_dead_draw() => {
+                self.message = "Neither side can win: it's a draw. Press q to quit.".to_string()
+            }
+            Score::Balanced(_) => (),
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let depth = env::args()
+        .nth(1)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(3);
+    let mut app = App::new(MinimaxEngine::from_config(
+        EngineConfig::default()
+            .with_depth(depth)
+            .with_time_limit(Duration::from_secs(1))
+            .with_transposition_table(1 << 16, ReplacementPolicy::default()),
+    ));
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        default_hook(info);
+    }));
+    let _guard = TerminalGuard::enter()?;
+    loop {
+        draw(&app)?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if key.code == KeyCode::Char('q') {
+                return Ok(());
+            }
+            app.handle(key.code);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_engine_evaluation_after_its_reply() {
+        let mut app = App::new(MinimaxEngine::new(2));
+        let before = render(&app);
+        assert_eq!(before.len(), BOARD_ROWS + 8);
+        assert!(before
+            .iter()
+            .any(|line| line.starts_with("The engine evaluates")));
+
+        app.handle(KeyCode::Char('3'));
+        app.handle(KeyCode::Enter);
+        assert_eq!(app.state.turn(), Player::White);
+        let eval = app.eval.expect("the engine searched before replying");
+        let after = render(&app);
+        assert!(after.contains(&eval_bar(eval, 20)));
+        assert!(after.iter().any(|line| line.contains("replied")));
+
+        app.handle(KeyCode::Char('u'));
+        assert_eq!(app.eval, None);
+    }
+}
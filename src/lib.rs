@@ -0,0 +1,6650 @@
+//! Game engine for the board game Gobblet.
+//!
+//! Randomized features never use global or thread-local randomness: every
+//! stochastic API takes an injected `&mut impl Rng`, so seeding that RNG makes
+//! games reproducible.
+
+use std::{
+    array,
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, VecDeque},
+    error::Error,
+    fmt::{self, Display},
+    iter, mem, slice,
+    str::FromStr,
+    sync::{Arc, LazyLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub mod position_key;
+pub mod puzzle;
+pub mod record;
+pub mod transposition;
+pub mod zobrist;
+
+use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+use record::MoveGrade;
+use transposition::{Bound, ReplacementPolicy, TranspositionTable, TtEntry};
+use zobrist::ZobristKeys;
+
+pub const NUM_SIZES: usize = 4;
+pub const NUM_EACH_SIZE: i32 = 3;
+pub const BOARD_ROWS: usize = 4;
+pub const BOARD_COLS: usize = 4;
+/// Diagonal lines only exist on square boards.
+pub const SQUARE_BOARD: bool = BOARD_ROWS == BOARD_COLS;
+/// How far `GameState::is_dead_draw` looks ahead for a decisive line.
+pub const DEAD_DRAW_PLIES: u32 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Color {
+    Empty,
+    White,
+    Black,
+}
+
+impl Color {
+    /// The opposing side. Only meaningful for `White` and `Black`; `Empty` is a
+    /// bug in the caller, caught in debug builds and answered with `White` otherwise.
+    pub fn other(self) -> Color {
+        debug_assert!(self != Color::Empty, "Color::other called on Empty");
+        if self == Color::White {
+            Color::Black
+        } else {
+            Color::White
+        }
+    }
+}
+
+/// One of the two sides. Cells use `Color`, which can also be `Empty`; whose turn
+/// it is and who owns a reserve or a win is always a `Player`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Player {
+    White,
+    Black,
+}
+
+impl Player {
+    pub fn other(self) -> Player {
+        match self {
+            Player::White => Player::Black,
+            Player::Black => Player::White,
+        }
+    }
+
+    /// The color of this player's pieces.
+    pub fn color(self) -> Color {
+        match self {
+            Player::White => Color::White,
+            Player::Black => Color::Black,
+        }
+    }
+}
+
+impl From<Player> for Color {
+    fn from(player: Player) -> Color {
+        player.color()
+    }
+}
+
+impl Color {
+    /// The player whose pieces have this color, `None` for `Empty`.
+    pub fn player(self) -> Option<Player> {
+        match self {
+            Color::White => Some(Player::White),
+            Color::Black => Some(Player::Black),
+            Color::Empty => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Stack {
+    /// The pieces are stored in an array of sizes, where if an element of the array
+    /// is a non-empty color, then a piece of that color with the size equal to the index
+    /// is present in the stack.
+    pieces: [Color; NUM_SIZES],
+}
+
+impl Stack {
+    pub fn empty() -> Stack {
+        Stack {
+            pieces: [Color::Empty; NUM_SIZES],
+        }
+    }
+
+    /// Return the next valid space where a piece would go.
+    /// If this value is equal to NUM_SIZES, the stack is full.
+    pub fn top(&self) -> usize {
+        for i in (0..NUM_SIZES).rev() {
+            if self.pieces[i] != Color::Empty {
+                return i + 1;
+            }
+        }
+        0
+    }
+
+    /// How many more pieces the stack can hold, each covering the last:
+    /// `NUM_SIZES` when empty, 0 once the largest size is on top.
+    pub fn capacity_remaining(&self) -> usize {
+        NUM_SIZES - self.top()
+    }
+
+    /// How many pieces the stack holds, covered or not.
+    pub fn height(&self) -> usize {
+        self.pieces
+            .iter()
+            .filter(|&&color| color != Color::Empty)
+            .count()
+    }
+
+    /// The pieces in the stack as `(color, size)`, bottom to top.
+    pub fn contents(&self) -> Vec<(Color, usize)> {
+        self.pieces
+            .iter()
+            .enumerate()
+            .filter(|(_, &color)| color != Color::Empty)
+            .map(|(size, &color)| (color, size))
+            .collect()
+    }
+
+    /// The color of the piece directly under the top one, `Color::Empty` if the top
+    /// piece is alone or the stack is empty.
+    fn color_below_top(&self) -> Color {
+        let top = self.top();
+        self.pieces[..top.saturating_sub(1)]
+            .iter()
+            .rev()
+            .copied()
+            .find(|&color| color != Color::Empty)
+            .unwrap_or(Color::Empty)
+    }
+
+    pub fn top_color(&self) -> Color {
+        for color in self.pieces.into_iter().rev() {
+            if color != Color::Empty {
+                return color;
+            }
+        }
+        Color::Empty
+    }
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Stack::empty()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Board {
+    contents: [[Stack; BOARD_COLS]; BOARD_ROWS],
+}
+
+impl Board {
+    pub fn empty() -> Board {
+        Board {
+            contents: Default::default(),
+        }
+    }
+
+    pub fn stack(&self, (row, col): Position) -> &Stack {
+        &self.contents[row][col]
+    }
+}
+
+/// Full equality also compares the rules and move history; use
+/// `GameState::position_eq` to compare positions only.
+#[derive(Clone, PartialEq, Eq)]
+pub struct GameState {
+    // White and black pieces store how many of each size there are,
+    // where the index is the size.
+    white_pieces: [i32; NUM_SIZES],
+    black_pieces: [i32; NUM_SIZES],
+
+    board: Board,
+    turn: Player,
+    /// The Zobrist hash of the board, reserves and side to move, updated
+    /// incrementally as moves are made and unmade.
+    hash: u64,
+    /// The color on top of each cell, updated along with `hash`.
+    tops: [[Color; BOARD_COLS]; BOARD_ROWS],
+
+    rules: RuleSet,
+    /// The last two moves played, most recent first.
+    last_moves: [Option<GameMove>; 2],
+
+    /// One entry per move played with `make_move`, oldest first.
+    history: Vec<HistoryEntry>,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct HistoryEntry {
+    game_move: GameMove,
+    /// Zobrist hash of the position the move was played from.
+    hash: u64,
+    /// The move that dropped out of `last_moves` when this one was played.
+    evicted_move: Option<GameMove>,
+}
+
+/// Optional variant rules. The default is the standard game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RuleSet {
+    /// A player may not move a piece straight back to where their previous move
+    /// took it from.
+    pub forbid_reversal: bool,
+    /// Completing a diagonal wins. Has no effect when no diagonal is
+    /// `win_length` cells long, as on non-square boards by default.
+    pub diagonal_wins: bool,
+    /// Pieces from the reserve may cover other pieces. When off, they can only be
+    /// placed on empty cells, and only pieces already on the board can gobble.
+    pub reserve_can_gobble: bool,
+    /// A player may pass instead of moving, though not straight after the
+    /// opponent passed. A pass never completes a line for the passer.
+    pub allow_pass: bool,
+    /// The sizes that may be placed from the reserve, for simplified variants.
+    /// Reserve pieces of other sizes stay out of play.
+    pub usable_sizes: [bool; NUM_SIZES],
+    /// Pieces of sizes left out of `usable_sizes` that are already on the board,
+    /// e.g. from a set-up position, may still be moved.
+    pub move_unusable_sizes: bool,
+    /// The most pieces a stack may hold. No move builds a stack taller than this,
+    /// even if a larger piece is available; `NUM_SIZES` leaves stacks uncapped.
+    pub max_stack_height: usize,
+    /// How many cells in a row, column or diagonal one color must top to win, from
+    /// 1 up. Rows and columns shorter than this must be topped in full, and
+    /// diagonals only count when the board fits one this long.
+    pub win_length: usize,
+    /// When the side to move can gobble one of the opponent's pieces, it must;
+    /// every other move, a pass included, is then illegal.
+    pub mandatory_capture: bool,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet {
+            forbid_reversal: false,
+            diagonal_wins: true,
+            reserve_can_gobble: true,
+            allow_pass: false,
+            usable_sizes: [true; NUM_SIZES],
+            move_unusable_sizes: true,
+            max_stack_height: NUM_SIZES,
+            win_length: BOARD_ROWS.max(BOARD_COLS),
+            mandatory_capture: false,
+        }
+    }
+}
+
+impl GameState {
+    pub fn new() -> GameState {
+        GameState::with_reserves([NUM_EACH_SIZE; NUM_SIZES], [NUM_EACH_SIZE; NUM_SIZES])
+    }
+
+    /// The standard starting position with `turn` to move first.
+    pub fn new_with_turn(turn: Player) -> GameState {
+        let mut state = GameState::new();
+        state.turn = turn;
+        state.resync();
+        state
+    }
+
+    /// Start from an empty board with a custom number of reserve pieces of each size
+    /// for each player, e.g. to handicap the stronger player.
+    pub fn with_reserves(white: [i32; NUM_SIZES], black: [i32; NUM_SIZES]) -> GameState {
+        let mut state = GameState {
+            white_pieces: white,
+            black_pieces: black,
+            board: Board::empty(),
+            turn: Player::White,
+            hash: 0,
+            tops: [[Color::Empty; BOARD_COLS]; BOARD_ROWS],
+            rules: RuleSet::default(),
+            last_moves: [None; 2],
+            history: Vec::new(),
+        };
+        state.resync();
+        state
+    }
+
+    /// Play this game under `rules` instead of the standard rules.
+    ///
+    /// Panics if `rules.win_length` is not between 1 and the longer side of the board.
+    pub fn with_rules(self, rules: RuleSet) -> GameState {
+        let longest = BOARD_ROWS.max(BOARD_COLS);
+        assert!(
+            (1..=longest).contains(&rules.win_length),
+            "a win length of {} doesn't fit a board whose longest line has {longest} cells",
+            rules.win_length
+        );
+        let mut state = GameState { rules, ..self };
+        // `forbid_reversal` decides whether the last relocation is hashed.
+        state.resync();
+        state
+    }
+
+    pub fn rules(&self) -> RuleSet {
+        self.rules
+    }
+
+    fn reserves_mut(&mut self, player: Player) -> &mut [i32; NUM_SIZES] {
+        if player == Player::White {
+            &mut self.white_pieces
+        } else {
+            &mut self.black_pieces
+        }
+    }
+
+    /// Parse a position string: rows from row 1 to row 4 separated by `/`, cells
+    /// separated by `,`. A cell is `.` when empty, otherwise its pieces from bottom
+    /// to top joined by `|`, each written as a color letter and a size (`W3`, `b0`).
+    /// Reserves are whatever of the standard stock is not on the board.
+    pub fn from_position(text: &str, turn: Player) -> Result<GameState, PositionError> {
+        let rows: Vec<&str> = text.trim().split('/').collect();
+        if rows.len() != BOARD_ROWS {
+            return Err(PositionError::RowCount(rows.len()));
+        }
+        let mut grid = [["."; BOARD_COLS]; BOARD_ROWS];
+        for (row, row_text) in rows.into_iter().enumerate() {
+            let cells: Vec<&str> = row_text.split(',').collect();
+            if cells.len() != BOARD_COLS {
+                return Err(PositionError::CellCount {
+                    row,
+                    count: cells.len(),
+                });
+            }
+            grid[row].copy_from_slice(&cells);
+        }
+        GameState::from_grid(grid, turn)
+    }
+
+    /// Play `moves` from the starting position with `first` to move, checking each
+    /// one. On an illegal move, returns its index and why it is illegal.
+    pub fn from_move_list(
+        moves: &[GameMove],
+        first: Player,
+    ) -> Result<GameState, (usize, MoveError)> {
+        let mut state = GameState::new_with_turn(first);
+        for (index, &game_move) in moves.iter().enumerate() {
+            state
+                .check_move(game_move)
+                .map_err(|error| (index, error))?;
+            state.make_move(game_move);
+        }
+        Ok(state)
+    }
+
+    /// Build a position from a grid of cells in the `from_position` cell format,
+    /// for writing readable fixtures:
+    ///
+    /// ```text
+    /// GameState::from_grid(
+    ///     [
+    ///         ["W3", ".", ".", "."],
+    ///         [".", "b0|W2", ".", "."],
+    ///         [".", ".", ".", "."],
+    ///         [".", ".", ".", "B3"],
+    ///     ],
+    ///     Player::Black,
+    /// )
+    /// ```
+    pub fn from_grid(
+        grid: [[&str; BOARD_COLS]; BOARD_ROWS],
+        turn: Player,
+    ) -> Result<GameState, PositionError> {
+        let mut state = GameState::new();
+        state.turn = turn;
+        for (row, cells) in grid.into_iter().enumerate() {
+            for (col, cell) in cells.into_iter().enumerate() {
+                state.set_cell((row, col), cell.trim())?;
+            }
+        }
+        state.resync();
+        Ok(state)
+    }
+
+    /// Put the pieces described by `cell` at `position`, taking them from the reserves.
+    fn set_cell(&mut self, (row, col): Position, cell: &str) -> Result<(), PositionError> {
+        if cell == "." {
+            return Ok(());
+        }
+        let mut previous_size = None;
+        for piece in cell.split('|') {
+            let invalid = || PositionError::InvalidPiece(piece.to_string());
+            let mut chars = piece.chars();
+            let player = match chars.next().map(|c| c.to_ascii_uppercase()) {
+                Some('W') => Player::White,
+                Some('B') => Player::Black,
+                _ => return Err(invalid()),
+            };
+            let size: usize = chars.as_str().parse().map_err(|_| invalid())?;
+            if size >= NUM_SIZES {
+                return Err(invalid());
+            }
+            if previous_size.is_some_and(|previous| previous >= size) {
+                return Err(PositionError::StackOrder((row, col)));
+            }
+            previous_size = Some(size);
+            self.board.contents[row][col].pieces[size] = player.color();
+            let reserves = self.reserves_mut(player);
+            reserves[size] -= 1;
+            if reserves[size] < 0 {
+                return Err(PositionError::TooManyPieces { player, size });
+            }
+        }
+        Ok(())
+    }
+
+    /// The board in the format accepted by `from_position`.
+    pub fn position_string(&self) -> String {
+        self.board
+            .contents
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|stack| {
+                        let pieces: Vec<String> = stack
+                            .contents()
+                            .into_iter()
+                            .map(|(color, size)| {
+                                let letter = if color == Color::White { 'W' } else { 'B' };
+                                format!("{letter}{size}")
+                            })
+                            .collect();
+                        if pieces.is_empty() {
+                            ".".to_string()
+                        } else {
+                            pieces.join("|")
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// A short, stable identifier for the position for logging: its Zobrist hash
+    /// written as 11 base-62 digits.
+    pub fn short_id(&self) -> String {
+        const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+        let mut hash = self.zobrist();
+        let mut id = [b'0'; 11];
+        for digit in id.iter_mut().rev() {
+            *digit = DIGITS[(hash % 62) as usize];
+            hash /= 62;
+        }
+        String::from_utf8(id.to_vec()).unwrap()
+    }
+
+    pub fn stack_at(&self, position: Position) -> &Stack {
+        self.board.stack(position)
+    }
+
+    /// The color and size of the piece on top at `position`, or `None` if the cell
+    /// is empty.
+    pub fn top_piece(&self, position: Position) -> Option<(Color, usize)> {
+        let stack = self.board.stack(position);
+        match stack.top() {
+            0 => None,
+            top => Some((stack.top_color(), top - 1)),
+        }
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// The side to move.
+    pub fn turn(&self) -> Player {
+        self.turn
+    }
+
+    /// How many reserve pieces of each size `player` has left.
+    pub fn reserves(&self, player: Player) -> [i32; NUM_SIZES] {
+        if player == Player::White {
+            self.white_pieces
+        } else {
+            self.black_pieces
+        }
+    }
+
+    /// Whether the board, reserves and side to move match, however each position
+    /// was reached, along with what the last moves forbid: passing again, or under
+    /// `RuleSet::forbid_reversal`, reversing a relocation. This is the equality
+    /// `zobrist` and the transposition table use.
+    pub fn position_eq(&self, other: &GameState) -> bool {
+        self.board == other.board
+            && self.white_pieces == other.white_pieces
+            && self.black_pieces == other.black_pieces
+            && self.turn == other.turn
+            && self.last_move_state() == other.last_move_state()
+    }
+
+    /// Whether the last move was a pass, and the relocation the side to move may
+    /// not reverse, if any: the parts of the last moves that affect legality.
+    fn last_move_state(&self) -> (bool, Option<(Position, Position)>) {
+        let reversible = match self.last_moves[1] {
+            Some(GameMove::Move { source, dest }) if self.rules.forbid_reversal => {
+                Some((source, dest))
+            }
+            _ => None,
+        };
+        (self.last_moves[0] == Some(GameMove::Pass), reversible)
+    }
+
+    /// The keys of `last_move_state` in `keys`.
+    fn last_move_key(&self, keys: &ZobristKeys) -> u64 {
+        let (passed, reversible) = self.last_move_state();
+        let mut hash = 0;
+        if passed {
+            hash ^= keys.passed;
+        }
+        if let Some(((source_row, source_col), (dest_row, dest_col))) = reversible {
+            hash ^= keys.moved_from[source_row][source_col] ^ keys.moved_to[dest_row][dest_col];
+        }
+        hash
+    }
+
+    /// A deterministic hash of the board, reserves, side to move and what the last
+    /// moves forbid (see `position_eq`). Equal positions always hash equal, across
+    /// runs as well, so the value can key external caches.
+    pub fn zobrist(&self) -> u64 {
+        self.hash
+    }
+
+    /// Recompute the hash and the top colors from scratch after changing the
+    /// position other than through `apply_move` and `unmake_move`.
+    fn resync(&mut self) {
+        self.hash = self.full_zobrist();
+        self.tops = self.full_top_colors();
+    }
+
+    /// The hash `zobrist` keeps up to date, computed from the whole position.
+    fn full_zobrist(&self) -> u64 {
+        self.zobrist_with(&zobrist::KEYS)
+    }
+
+    /// The hash `zobrist` would give with `keys` in place of the default keys,
+    /// computed from the whole position.
+    pub fn zobrist_with(&self, keys: &ZobristKeys) -> u64 {
+        let mut hash = 0;
+        for (row, stacks) in self.board.contents.iter().enumerate() {
+            for (col, stack) in stacks.iter().enumerate() {
+                for (size, &color) in stack.pieces.iter().enumerate() {
+                    match color {
+                        Color::White => hash ^= keys.pieces[row][col][size][0],
+                        Color::Black => hash ^= keys.pieces[row][col][size][1],
+                        Color::Empty => (),
+                    }
+                }
+            }
+        }
+        for size in 0..NUM_SIZES {
+            hash ^= keys.reserves[0][size].rotate_left(self.white_pieces[size] as u32);
+            hash ^= keys.reserves[1][size].rotate_left(self.black_pieces[size] as u32);
+        }
+        if self.turn == Player::Black {
+            hash ^= keys.black_to_move;
+        }
+        hash ^ self.last_move_key(keys)
+    }
+
+    fn next_turn(&mut self) {
+        self.turn = self.turn.other();
+        self.hash ^= zobrist::KEYS.black_to_move;
+    }
+
+    /// Set the piece of `size` at `position` to `color`, updating the hash.
+    fn set_piece(&mut self, (row, col): Position, size: usize, color: Color) {
+        let keys = &zobrist::KEYS.pieces[row][col][size];
+        let slot = &mut self.board.contents[row][col].pieces[size];
+        for old_or_new in [*slot, color] {
+            match old_or_new {
+                Color::White => self.hash ^= keys[0],
+                Color::Black => self.hash ^= keys[1],
+                Color::Empty => (),
+            }
+        }
+        *slot = color;
+        self.tops[row][col] = self.board.contents[row][col].top_color();
+    }
+
+    /// Add `delta` to `player`'s reserve of `size`, updating the hash.
+    fn adjust_reserve(&mut self, player: Player, size: usize, delta: i32) {
+        let key = zobrist::KEYS.reserves[player as usize][size];
+        let count = &mut self.reserves_mut(player)[size];
+        let old = *count;
+        *count += delta;
+        let new = *count;
+        self.hash ^= key.rotate_left(old as u32) ^ key.rotate_left(new as u32);
+    }
+
+    /// In debug builds, check the incrementally updated hash and top colors
+    /// against a full recompute, so a bookkeeping slip can't silently corrupt
+    /// hash-keyed tables or win detection.
+    fn debug_check_incremental(&self) {
+        debug_assert_eq!(
+            self.hash,
+            self.full_zobrist(),
+            "incremental Zobrist hash diverged in {}",
+            self.position_string()
+        );
+        debug_assert_eq!(
+            self.tops,
+            self.full_top_colors(),
+            "incremental top colors diverged in {}",
+            self.position_string()
+        );
+    }
+
+    /// Play `game_move` without checking it against the rules; see `try_apply` for a
+    /// checked version. Debug builds panic on moves that would index off the board.
+    /// The move is not recorded in the history, so it can't be taken back with
+    /// `unmake_move` or `undo_last`; don't mix it with `make_move` on one state.
+    pub fn apply_move(&mut self, game_move: GameMove) {
+        let in_bounds = |(row, col): Position| row < BOARD_ROWS && col < BOARD_COLS;
+        debug_assert!(
+            match game_move {
+                GameMove::Place { size, dest } => size < NUM_SIZES && in_bounds(dest),
+                GameMove::Move { source, dest } => in_bounds(source) && in_bounds(dest),
+                GameMove::Pass => true,
+            },
+            "apply_move: {}",
+            MoveError::OutOfBounds(game_move)
+        );
+        match game_move {
+            GameMove::Move { source, .. } => debug_assert!(
+                self.board.stack(source).top() > 0,
+                "apply_move: {}",
+                MoveError::EmptySource(source)
+            ),
+            GameMove::Place { size, .. } => debug_assert!(
+                self.reserves(self.turn)[size] > 0,
+                "apply_move: {}",
+                MoveError::NoReserve {
+                    player: self.turn,
+                    size
+                }
+            ),
+            GameMove::Pass => (),
+        }
+        match game_move {
+            GameMove::Move { source, dest } => {
+                let stack = self.board.stack(source);
+                let (current_top, color) = (stack.top() - 1, stack.top_color());
+                self.set_piece(dest, current_top, color);
+                self.set_piece(source, current_top, Color::Empty);
+            }
+            GameMove::Place { size, dest } => {
+                self.set_piece(dest, size, self.turn.color());
+                self.adjust_reserve(self.turn, size, -1);
+            }
+            GameMove::Pass => (),
+        }
+        self.hash ^= self.last_move_key(&zobrist::KEYS);
+        self.last_moves = [Some(game_move), self.last_moves[0]];
+        self.hash ^= self.last_move_key(&zobrist::KEYS);
+        self.next_turn();
+        self.debug_check_incremental();
+    }
+
+    /// Play `game_move`, recording the position it was played from so repetitions
+    /// can be detected. Must be undone with `unmake_move`.
+    pub fn make_move(&mut self, game_move: GameMove) {
+        self.history.push(HistoryEntry {
+            game_move,
+            hash: self.zobrist(),
+            evicted_move: self.last_moves[1],
+        });
+        self.apply_move(game_move);
+    }
+
+    /// Take back `game_move`, which must be the last move made with `make_move`.
+    /// Debug builds panic if it wasn't, for instance after `apply_move`.
+    pub fn unmake_move(&mut self, game_move: GameMove) {
+        self.next_turn();
+        match game_move {
+            GameMove::Move { source, dest } => {
+                let stack = self.board.stack(dest);
+                let (current_top, color) = (stack.top() - 1, stack.top_color());
+                self.set_piece(source, current_top, color);
+                self.set_piece(dest, current_top, Color::Empty);
+            }
+            GameMove::Place { size, dest } => {
+                self.set_piece(dest, size, Color::Empty);
+                self.adjust_reserve(self.turn, size, 1);
+            }
+            GameMove::Pass => (),
+        }
+        let entry = self.history.pop();
+        self.hash ^= self.last_move_key(&zobrist::KEYS);
+        self.last_moves = [
+            self.last_moves[1],
+            entry.as_ref().and_then(|entry| entry.evicted_move),
+        ];
+        self.hash ^= self.last_move_key(&zobrist::KEYS);
+        self.debug_check_incremental();
+        debug_assert!(
+            entry.is_some_and(|entry| entry.game_move == game_move && entry.hash == self.zobrist()),
+            "unmake_move: {game_move} was not the last move made with make_move"
+        );
+    }
+
+    /// Take back the last move made with `make_move` and return it, or `None` if
+    /// there is nothing to take back. Moves played with `apply_move` are not recorded.
+    pub fn undo_last(&mut self) -> Option<GameMove> {
+        let game_move = self.history.last()?.game_move;
+        self.unmake_move(game_move);
+        Some(game_move)
+    }
+
+    /// Whether every legal move leaves the side to move with a strictly worse static
+    /// evaluation than it has now, a zugzwang-like position. Returns `false` when
+    /// there are no legal moves, including when the game is already decided.
+    pub fn all_moves_worsen(&self, weights: &EvalWeights) -> bool {
+        let moves = self.legal_moves();
+        if moves.is_empty() {
+            return false;
+        }
+        let mover = self.turn;
+        let current = self.raw_score_with(weights);
+        let mut scratch = self.clone();
+        moves.into_iter().all(|game_move| {
+            scratch.make_move(game_move);
+            let after = scratch.raw_score_with(weights);
+            scratch.unmake_move(game_move);
+            if mover == Player::White {
+                after < current
+            } else {
+                after > current
+            }
+        })
+    }
+
+    /// Every legal move with its value searched `depth` plies deep counting the
+    /// move itself, best for the side to move first. Equally scored moves stay in
+    /// generation order.
+    pub fn analyze(&self, depth: i32) -> Vec<(GameMove, Score)> {
+        let mut scratch = self.clone();
+        let scored = self
+            .legal_moves()
+            .into_iter()
+            .map(|game_move| (game_move, scratch.analyze_move(game_move, depth)))
+            .collect();
+        self.sort_analysis(scored)
+    }
+
+    /// `analyze`, searching only `distinct_moves` and giving every other move the
+    /// score of the move it mirrors. The result is the same as `analyze`'s.
+    pub fn analyze_symmetric(&self, depth: i32) -> Vec<(GameMove, Score)> {
+        let mut scratch = self.clone();
+        let distinct: Vec<(GameMove, Score)> = self
+            .distinct_moves()
+            .into_iter()
+            .map(|game_move| (game_move, scratch.analyze_move(game_move, depth)))
+            .collect();
+        let symmetries = self.self_symmetries();
+        let scored = self
+            .legal_moves()
+            .into_iter()
+            .map(|game_move| {
+                let score = distinct
+                    .iter()
+                    .find(|&&(representative, _)| {
+                        symmetries
+                            .iter()
+                            .any(|&symmetry| representative.transform(symmetry) == game_move)
+                    })
+                    .map(|&(_, score)| score)
+                    .unwrap();
+                (game_move, score)
+            })
+            .collect();
+        self.sort_analysis(scored)
+    }
+
+    /// `analyze` with the root moves split across `threads` threads. The result is
+    /// the same as `analyze`, order included, unless a worker thread panics.
+    pub fn analyze_parallel(
+        &self,
+        depth: i32,
+        threads: usize,
+    ) -> Result<Vec<(GameMove, Score)>, EngineError> {
+        if threads <= 1 {
+            return Ok(self.analyze(depth));
+        }
+        let moves = self.legal_moves();
+        let chunk_size = moves.len().div_ceil(threads).max(1);
+        let scored = thread::scope(|scope| {
+            let workers: Vec<_> = moves
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        let mut scratch = self.clone();
+                        chunk
+                            .iter()
+                            .map(|&game_move| (game_move, scratch.analyze_move(game_move, depth)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().map_err(|_| EngineError::WorkerPanicked))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        Ok(self.sort_analysis(scored.into_iter().flatten().collect()))
+    }
+
+    /// How `game_move` compares with the best move here, both searched as by
+    /// `analyze`. For practice drills: a move graded `MoveGrade::Best` is as good
+    /// as the engine's top choice.
+    pub fn grade_move(&self, game_move: &GameMove, depth: i32) -> Result<MoveGrade, MoveError> {
+        self.check_move(*game_move)?;
+        let analysis = self.analyze(depth);
+        let best = analysis[0].1;
+        let played = analysis
+            .iter()
+            .find(|(candidate, _)| candidate == game_move)
+            .map(|&(_, score)| score)
+            .unwrap();
+        Ok(MoveGrade::of(self.turn, best, played))
+    }
+
+    fn analyze_move(&mut self, game_move: GameMove, depth: i32) -> Score {
+        self.make_move(game_move);
+        let score = alpha_beta(self, depth - 1, Score::MIN, Score::MAX, None);
+        self.unmake_move(game_move);
+        score.backed_up()
+    }
+
+    /// Sort `scored` best first for the side to move, keeping ties in order.
+    fn sort_analysis(&self, mut scored: Vec<(GameMove, Score)>) -> Vec<(GameMove, Score)> {
+        if self.turn == Player::White {
+            scored.sort_by_key(|&(_, score)| Reverse(score));
+        } else {
+            scored.sort_by_key(|&(_, score)| score);
+        }
+        scored
+    }
+
+    /// Whether this position already occurred earlier in the history.
+    pub fn is_repetition_draw(&self) -> bool {
+        let hash = self.zobrist();
+        self.history.iter().any(|entry| entry.hash == hash)
+    }
+
+    /// The outcome, if the game is over: someone has won, or the side to move has
+    /// no legal moves. A dead draw (see `is_dead_draw`) is not over, since the
+    /// players can still move; callers that want to stop early check it separately.
+    pub fn result(&self) -> Option<GameResult> {
+        if let Some(winner) = self.winner() {
+            return Some(GameResult::Win(winner));
+        }
+        if self.legal_move_count() == 0 {
+            return Some(GameResult::Draw);
+        }
+        None
+    }
+
+    /// Whether the game can no longer be decided: both reserves are exhausted (or
+    /// hold only sizes outside `RuleSet::usable_sizes`) and
+    /// no sequence of up to `DEAD_DRAW_PLIES` moves completes a line for either side.
+    /// The lookahead is bounded, so a win that takes longer to set up is missed.
+    pub fn is_dead_draw(&self) -> bool {
+        let reserves_empty = |player| {
+            self.reserves(player)
+                .iter()
+                .zip(self.rules.usable_sizes)
+                .all(|(&count, usable)| count == 0 || !usable)
+        };
+        if !reserves_empty(Player::White) || !reserves_empty(Player::Black) {
+            return false;
+        }
+        if self.raw_score().is_decisive() {
+            return false;
+        }
+        !self.clone().can_decide_within(DEAD_DRAW_PLIES)
+    }
+
+    fn can_decide_within(&mut self, plies: u32) -> bool {
+        if plies == 0 {
+            return false;
+        }
+        self.legal_moves().into_iter().any(|game_move| {
+            self.make_move(game_move);
+            let decided = self.raw_score().is_decisive() || self.can_decide_within(plies - 1);
+            self.unmake_move(game_move);
+            decided
+        })
+    }
+
+    /// Check `game_move` against the rules, explaining why it is illegal if it is.
+    /// Accepts exactly the moves `legal_moves` generates.
+    pub fn check_move(&self, game_move: GameMove) -> Result<(), MoveError> {
+        let in_bounds = |(row, col): Position| row < BOARD_ROWS && col < BOARD_COLS;
+        let (dest_row, dest_col) = match game_move {
+            GameMove::Place { size, dest } if size < NUM_SIZES && in_bounds(dest) => dest,
+            GameMove::Move { source, dest } if in_bounds(source) && in_bounds(dest) => dest,
+            GameMove::Pass => {
+                if let Some(winner) = self.winner() {
+                    return Err(MoveError::GameOver(winner));
+                }
+                return if !self.can_pass() {
+                    Err(MoveError::PassNotAllowed)
+                } else if self.capture_required() {
+                    Err(MoveError::CaptureRequired)
+                } else {
+                    Ok(())
+                };
+            }
+            _ => return Err(MoveError::OutOfBounds(game_move)),
+        };
+        if let Some(winner) = self.winner() {
+            return Err(MoveError::GameOver(winner));
+        }
+        let dest_top = self.board.contents[dest_row][dest_col].top();
+
+        let have = match game_move {
+            GameMove::Place { size, .. } => {
+                if self.reserves(self.turn)[size] <= 0 {
+                    return Err(MoveError::NoReserve {
+                        player: self.turn,
+                        size,
+                    });
+                }
+                if !self.rules.usable_sizes[size] {
+                    return Err(MoveError::SizeNotInPlay(size));
+                }
+                if !self.rules.reserve_can_gobble && dest_top > 0 {
+                    return Err(MoveError::ReserveCannotGobble((dest_row, dest_col)));
+                }
+                size
+            }
+            GameMove::Move {
+                source: source @ (source_row, source_col),
+                dest,
+            } => {
+                if source == dest {
+                    return Err(MoveError::SamePosition(source));
+                }
+                let stack = &self.board.contents[source_row][source_col];
+                if stack.top() == 0 {
+                    return Err(MoveError::EmptySource(source));
+                }
+                if stack.top_color() != self.turn.color() {
+                    return Err(MoveError::NotYourPiece(source));
+                }
+                if !self.can_move_size(stack.top() - 1) {
+                    return Err(MoveError::SizeNotInPlay(stack.top() - 1));
+                }
+                if self.rules.forbid_reversal
+                    && self.last_moves[1]
+                        == Some(GameMove::Move {
+                            source: dest,
+                            dest: source,
+                        })
+                {
+                    return Err(MoveError::ReversesLastMove);
+                }
+                stack.top() - 1
+            }
+            GameMove::Pass => unreachable!("passes are checked above"),
+        };
+
+        if self.is_stack_full((dest_row, dest_col)) {
+            return Err(MoveError::DestinationFull((dest_row, dest_col)));
+        }
+        if have < dest_top {
+            return Err(MoveError::PieceTooSmall {
+                needed: dest_top,
+                have,
+            });
+        }
+        if !self.captures(game_move) && self.capture_required() {
+            return Err(MoveError::CaptureRequired);
+        }
+        Ok(())
+    }
+
+    /// Whether `game_move` can be played here, without playing it.
+    pub fn is_legal(&self, game_move: &GameMove) -> bool {
+        self.check_move(*game_move).is_ok()
+    }
+
+    /// Play `game_move` if it is legal, otherwise leave the position untouched.
+    pub fn try_apply(&mut self, game_move: GameMove) -> Result<(), MoveError> {
+        self.check_move(game_move)?;
+        self.make_move(game_move);
+        Ok(())
+    }
+
+    /// Parse `notation`, check the move is legal here and play it.
+    pub fn apply_notation(&mut self, notation: &str) -> Result<GameMove, ApplyError> {
+        let game_move: GameMove = notation.parse().map_err(ApplyError::Notation)?;
+        self.try_apply(game_move).map_err(ApplyError::Illegal)?;
+        Ok(game_move)
+    }
+
+    /// Call `visit` with every legal move in this position, in generation order.
+    /// This is the single source of truth for move generation; it never allocates.
+    pub fn for_each_move(&self, mut visit: impl FnMut(GameMove)) {
+        if self.capture_required() {
+            self.for_each_unforced_move(|game_move| {
+                if self.captures(game_move) {
+                    visit(game_move);
+                }
+            });
+        } else {
+            self.for_each_unforced_move(visit);
+        }
+    }
+
+    /// Whether `game_move` gobbles one of the opponent's pieces.
+    pub fn captures(&self, game_move: GameMove) -> bool {
+        game_move
+            .dest()
+            .is_some_and(|(row, col)| self.tops[row][col] == self.turn.other().color())
+    }
+
+    /// Whether `RuleSet::mandatory_capture` limits the side to move to captures.
+    fn capture_required(&self) -> bool {
+        if !self.rules.mandatory_capture {
+            return false;
+        }
+        let mut can_capture = false;
+        self.for_each_unforced_move(|game_move| can_capture |= self.captures(game_move));
+        can_capture
+    }
+
+    /// `for_each_move` ignoring `RuleSet::mandatory_capture`.
+    fn for_each_unforced_move(&self, mut visit: impl FnMut(GameMove)) {
+        // Play stops once someone has won.
+        if self.winner().is_some() {
+            return;
+        }
+        let available_pieces = if self.turn == Player::White {
+            self.white_pieces
+        } else {
+            self.black_pieces
+        };
+
+        let tops: [[usize; BOARD_COLS]; BOARD_ROWS] =
+            array::from_fn(|row| array::from_fn(|col| self.board.contents[row][col].top()));
+        // The relocation this player may not play, if any: their previous one reversed.
+        let reversal = match self.last_moves[1] {
+            Some(GameMove::Move { source, dest }) if self.rules.forbid_reversal => {
+                Some(GameMove::Move {
+                    source: dest,
+                    dest: source,
+                })
+            }
+            _ => None,
+        };
+
+        for (dest_row, dest_stack_row) in tops.into_iter().enumerate() {
+            for (dest_col, dest_top) in dest_stack_row.into_iter().enumerate() {
+                if self.is_stack_full((dest_row, dest_col)) {
+                    continue;
+                }
+                let placeable = dest_top == 0 || self.rules.reserve_can_gobble;
+                for (size, count) in available_pieces.into_iter().enumerate() {
+                    if placeable && count > 0 && size >= dest_top && self.rules.usable_sizes[size] {
+                        visit(GameMove::Place {
+                            size,
+                            dest: (dest_row, dest_col),
+                        });
+                    }
+                }
+
+                for (source_row, source_stack_row) in tops.into_iter().enumerate() {
+                    for (source_col, source_top) in source_stack_row.into_iter().enumerate() {
+                        let game_move = GameMove::Move {
+                            source: (source_row, source_col),
+                            dest: (dest_row, dest_col),
+                        };
+                        if source_top > dest_top
+                            && self.can_move_size(source_top - 1)
+                            && (source_row != dest_row || source_col != dest_col)
+                            && self.tops[source_row][source_col] == self.turn.color()
+                            && Some(game_move) != reversal
+                        {
+                            visit(game_move);
+                        }
+                    }
+                }
+            }
+        }
+
+        if self.can_pass() {
+            visit(GameMove::Pass);
+        }
+    }
+
+    /// Whether the rules let a piece of `size` already on the board be moved.
+    fn can_move_size(&self, size: usize) -> bool {
+        self.rules.move_unusable_sizes || self.rules.usable_sizes[size]
+    }
+
+    /// Whether the rules let the side to move pass: passing is allowed and the
+    /// opponent didn't just pass.
+    fn can_pass(&self) -> bool {
+        self.rules.allow_pass && self.last_moves[0] != Some(GameMove::Pass)
+    }
+
+    /// The legal moves, highest `ordering` priority first and in generation order
+    /// among equal priorities. Every move is ranked up front, but the moves are
+    /// only sorted as far as they are taken.
+    pub fn ordered_moves(&self, ordering: &dyn MoveOrdering) -> impl Iterator<Item = GameMove> {
+        let moves = self.legal_moves();
+        let mut queue: BinaryHeap<(i32, Reverse<usize>)> = moves
+            .iter()
+            .enumerate()
+            .map(|(index, &game_move)| (ordering.priority(self, game_move), Reverse(index)))
+            .collect();
+        iter::from_fn(move || queue.pop().map(|(_, Reverse(index))| moves[index]))
+    }
+
+    pub fn legal_moves(&self) -> Vec<GameMove> {
+        let mut moves = Vec::new();
+        self.for_each_move(|game_move| moves.push(game_move));
+        moves
+    }
+
+    /// At most `max` legal moves, the most promising first by `CoverFirst`, and
+    /// whether any were left out.
+    pub fn legal_moves_capped(&self, max: usize) -> (Vec<GameMove>, bool) {
+        let mut ordered = self.ordered_moves(&CoverFirst);
+        let moves: Vec<GameMove> = ordered.by_ref().take(max).collect();
+        (moves, ordered.next().is_some())
+    }
+
+    /// Legal moves, minus whatever `options` asks to leave out. The filters are
+    /// heuristics for weaker or faster players; they may drop good moves.
+    pub fn legal_moves_with(&self, options: &MoveOptions) -> Vec<GameMove> {
+        let smallest_in_stock = self
+            .reserves(self.turn)
+            .iter()
+            .zip(self.rules.usable_sizes)
+            .position(|(&count, usable)| count > 0 && usable);
+        let mut moves = Vec::new();
+        self.for_each_move(|game_move| {
+            if options.exclude_shuffles && self.is_shuffle(game_move) {
+                return;
+            }
+            if let GameMove::Place { size, dest } = game_move {
+                if options.smallest_on_empty
+                    && self.board.stack(dest).top() == 0
+                    && Some(size) != smallest_in_stock
+                {
+                    return;
+                }
+            }
+            moves.push(game_move);
+        });
+        if options.prefer_relocation {
+            let opponent = self.turn.other().color();
+            let relocation_dests: Vec<Position> = moves
+                .iter()
+                .filter_map(|&game_move| match game_move {
+                    GameMove::Move { source, dest }
+                        if self.board.stack(source).color_below_top() != opponent =>
+                    {
+                        Some(dest)
+                    }
+                    _ => None,
+                })
+                .collect();
+            moves.retain(|game_move| match game_move {
+                GameMove::Place { dest, .. } => !relocation_dests.contains(dest),
+                GameMove::Move { .. } | GameMove::Pass => true,
+            });
+        }
+        moves
+    }
+
+    /// The legal moves grouped by the size of the piece they play, each group in
+    /// generation order. A pass plays no piece and is left out.
+    pub fn moves_by_size(&self) -> [Vec<GameMove>; NUM_SIZES] {
+        let mut by_size: [Vec<GameMove>; NUM_SIZES] = Default::default();
+        self.for_each_move(|game_move| {
+            if let Some(size) = game_move.size(&self.board) {
+                by_size[size].push(game_move);
+            }
+        });
+        by_size
+    }
+
+    /// The legal moves, each with the static `raw_score` of the position it leads
+    /// to, e.g. to preview a move under the cursor. Scores are computed as the
+    /// iterator is advanced, making and unmaking each move on a single copy.
+    pub fn moves_with_preview(&self) -> impl Iterator<Item = (GameMove, Score)> {
+        let mut scratch = self.clone();
+        self.legal_moves().into_iter().map(move |game_move| {
+            scratch.make_move(game_move);
+            let score = scratch.raw_score();
+            scratch.unmake_move(game_move);
+            (game_move, score)
+        })
+    }
+
+    /// The legal moves with the ones that win on the spot first, each group in
+    /// generation order. The engine searches root moves in this order, so even a
+    /// one-ply search plays an immediate win whatever its other settings.
+    pub fn legal_moves_wins_first(&self) -> Vec<GameMove> {
+        let mut scratch = self.clone();
+        let (mut wins, others): (Vec<GameMove>, Vec<GameMove>) =
+            self.legal_moves().into_iter().partition(|&game_move| {
+                scratch.make_move(game_move);
+                let wins = scratch.winner().is_some();
+                scratch.unmake_move(game_move);
+                wins
+            });
+        wins.extend(others);
+        wins
+    }
+
+    /// The legal placements of a reserve piece of `size`.
+    pub fn moves_from_reserve(&self, size: usize) -> impl Iterator<Item = GameMove> {
+        self.legal_moves()
+            .into_iter()
+            .filter(move |game_move| match *game_move {
+                GameMove::Place { size: placed, .. } => placed == size,
+                GameMove::Move { .. } | GameMove::Pass => false,
+            })
+    }
+
+    /// The legal relocations of the piece on top of the stack at `position`.
+    pub fn moves_from_board(&self, position: Position) -> impl Iterator<Item = GameMove> {
+        self.legal_moves()
+            .into_iter()
+            .filter(move |game_move| match *game_move {
+                GameMove::Place { .. } | GameMove::Pass => false,
+                GameMove::Move { source, .. } => source == position,
+            })
+    }
+
+    /// The reserve sizes the side to move can place at `position`: sizes in stock and
+    /// larger than the piece currently on top there.
+    pub fn playable_sizes(&self, position: Position) -> Vec<usize> {
+        let mut sizes = Vec::new();
+        self.for_each_move(|game_move| {
+            if let GameMove::Place { size, dest } = game_move {
+                if dest == position {
+                    sizes.push(size);
+                }
+            }
+        });
+        sizes
+    }
+
+    /// Whether `game_move` is a relocation that leaves the top color of every cell
+    /// unchanged: the lifted piece uncovers one of the mover's own pieces and lands
+    /// on another.
+    pub fn is_shuffle(&self, game_move: GameMove) -> bool {
+        let GameMove::Move {
+            source: (source_row, source_col),
+            dest: (dest_row, dest_col),
+        } = game_move
+        else {
+            return false;
+        };
+        let own = self.turn.color();
+        self.board.stack((source_row, source_col)).color_below_top() == own
+            && self.board.stack((dest_row, dest_col)).top_color() == own
+    }
+
+    /// The number of legal moves, computed without allocating or cloning.
+    pub fn legal_move_count(&self) -> usize {
+        let mut count = 0;
+        self.for_each_move(|_| count += 1);
+        count
+    }
+
+    pub fn branch(&self) -> Vec<(GameMove, GameState)> {
+        let mut children = Vec::new();
+        self.for_each_move(|game_move| {
+            let mut new_state = self.clone();
+            new_state.apply_move(game_move);
+            children.push((game_move, new_state));
+        });
+        children
+    }
+
+    /// Pick a uniformly random legal move using the supplied `rng`.
+    /// Returns `None` if there are no legal moves.
+    pub fn random_move(&self, rng: &mut impl Rng) -> Option<(GameMove, GameState)> {
+        self.branch().into_iter().choose(rng)
+    }
+
+    /// Play uniformly random legal moves with `make_move` until someone wins, the
+    /// side to move is stuck, or `max_plies` moves have been played, and return the
+    /// outcome. A game cut off by the ply limit goes to the side the static
+    /// evaluation favors, and is a draw if it favors neither. The moves stay
+    /// played; `undo_last` takes them back. Picking moves never allocates, and the
+    /// same `rng` seed always gives the same playout.
+    pub fn rollout(&mut self, rng: &mut impl Rng, max_plies: usize) -> GameResult {
+        for _ in 0..max_plies {
+            if let Some(winner) = self.winner() {
+                return GameResult::Win(winner);
+            }
+            let count = self.legal_move_count();
+            if count == 0 {
+                return GameResult::Draw;
+            }
+            let mut pick = rng.gen_range(0..count);
+            let mut chosen = None;
+            self.for_each_move(|game_move| {
+                if pick == 0 {
+                    chosen = Some(game_move);
+                }
+                pick = pick.wrapping_sub(1);
+            });
+            self.make_move(chosen.unwrap());
+        }
+        match self.raw_score() {
+            Score::WhiteFavored(_) => GameResult::Win(Player::White),
+            Score::BlackFavored(_) => GameResult::Win(Player::Black),
+            Score::Balanced(score) => match score.cmp(&0) {
+                Ordering::Greater => GameResult::Win(Player::White),
+                Ordering::Less => GameResult::Win(Player::Black),
+                Ordering::Equal => GameResult::Draw,
+            },
+        }
+    }
+
+    /// A realistic mid-game position reached by playing `target_ply` uniformly random
+    /// moves from the start. Moves that end the game are avoided unless every move
+    /// does, so the result is only terminal when that was forced. The same `rng`
+    /// seed always gives the same position.
+    pub fn random_position(rng: &mut impl Rng, target_ply: usize) -> GameState {
+        let mut state = GameState::new();
+        for _ in 0..target_ply {
+            if state.raw_score().is_decisive() {
+                break;
+            }
+            let children = state.branch();
+            let quiet = children
+                .iter()
+                .filter(|(_, child)| !child.raw_score().is_decisive())
+                .map(|(game_move, _)| *game_move)
+                .choose(rng);
+            let game_move = match quiet {
+                Some(game_move) => game_move,
+                None => match children.into_iter().choose(rng) {
+                    Some((game_move, _)) => game_move,
+                    None => break,
+                },
+            };
+            state.make_move(game_move);
+        }
+        state
+    }
+
+    /// How many pieces of either color are on the board, covered or not.
+    pub fn pieces_on_board(&self) -> usize {
+        self.board
+            .contents
+            .iter()
+            .flatten()
+            .flat_map(|stack| stack.pieces)
+            .filter(|&color| color != Color::Empty)
+            .count()
+    }
+
+    /// The sum of `Stack::capacity_remaining` over every cell, each limited by
+    /// `RuleSet::max_stack_height`, a rough bound on how many more pieces can
+    /// still land on the board.
+    pub fn board_capacity(&self) -> usize {
+        self.board
+            .contents
+            .iter()
+            .flatten()
+            .map(|stack| {
+                let below_cap = self.rules.max_stack_height.saturating_sub(stack.height());
+                stack.capacity_remaining().min(below_cap)
+            })
+            .sum()
+    }
+
+    /// The phase of the game under the default thresholds.
+    pub fn phase(&self) -> Phase {
+        self.phase_with(&PhaseThresholds::default())
+    }
+
+    /// The phase of the game, judged by how many pieces have been placed.
+    pub fn phase_with(&self, thresholds: &PhaseThresholds) -> Phase {
+        let placed = self.pieces_on_board();
+        if placed >= thresholds.endgame {
+            Phase::Endgame
+        } else if placed >= thresholds.midgame {
+            Phase::Midgame
+        } else {
+            Phase::Opening
+        }
+    }
+
+    /// The color on top of each stack, `Color::Empty` for empty cells.
+    pub fn top_colors(&self) -> [[Color; BOARD_COLS]; BOARD_ROWS] {
+        self.tops
+    }
+
+    /// `top_colors` computed from the stacks instead of the maintained grid.
+    fn full_top_colors(&self) -> [[Color; BOARD_COLS]; BOARD_ROWS] {
+        array::from_fn(|row| array::from_fn(|col| self.board.contents[row][col].top_color()))
+    }
+
+    /// This position with the board transformed by `symmetry`. The history is not
+    /// carried over, since earlier positions were never seen in their transformed form.
+    ///
+    /// Panics if `symmetry` is not one of `Symmetry::all()` for this board shape.
+    pub fn transform(&self, symmetry: Symmetry) -> GameState {
+        assert!(
+            Symmetry::all().contains(&symmetry),
+            "{symmetry:?} needs a square board"
+        );
+        let mut transformed = GameState {
+            board: Board::empty(),
+            last_moves: self
+                .last_moves
+                .map(|game_move| game_move.map(|game_move| game_move.transform(symmetry))),
+            history: Vec::new(),
+            ..self.clone()
+        };
+        for row in 0..BOARD_ROWS {
+            for col in 0..BOARD_COLS {
+                let (new_row, new_col) = symmetry.apply((row, col));
+                transformed.board.contents[new_row][new_col] =
+                    self.board.contents[row][col].clone();
+            }
+        }
+        transformed.resync();
+        transformed
+    }
+
+    pub fn rotate90(&self) -> GameState {
+        self.transform(Symmetry::Rotate90)
+    }
+
+    pub fn rotate180(&self) -> GameState {
+        self.transform(Symmetry::Rotate180)
+    }
+
+    pub fn rotate270(&self) -> GameState {
+        self.transform(Symmetry::Rotate270)
+    }
+
+    pub fn reflect_horizontal(&self) -> GameState {
+        self.transform(Symmetry::ReflectHorizontal)
+    }
+
+    pub fn reflect_vertical(&self) -> GameState {
+        self.transform(Symmetry::ReflectVertical)
+    }
+
+    /// The other moves that `game_move` is equivalent to under a symmetry that
+    /// leaves this board unchanged, such as the four corner placements on an empty
+    /// board. They lead to mirror images of the same position.
+    pub fn symmetric_equivalents(&self, game_move: GameMove) -> Vec<GameMove> {
+        let mut equivalents = Vec::new();
+        for symmetry in self.self_symmetries() {
+            let image = game_move.transform(symmetry);
+            if image != game_move && !equivalents.contains(&image) {
+                equivalents.push(image);
+            }
+        }
+        equivalents
+    }
+
+    /// The symmetries that leave this position unchanged, including the previous
+    /// moves when `RuleSet::forbid_reversal` makes them matter to legality.
+    fn self_symmetries(&self) -> Vec<Symmetry> {
+        let board = self.position_string();
+        Symmetry::all()
+            .iter()
+            .copied()
+            .filter(|&symmetry| {
+                let transformed = self.transform(symmetry);
+                transformed.position_string() == board
+                    && (!self.rules.forbid_reversal || transformed.last_moves == self.last_moves)
+            })
+            .collect()
+    }
+
+    /// The legal moves, keeping only the first in generation order of each set of
+    /// moves that `symmetric_equivalents` relates. The others lead to mirror images
+    /// of the positions these lead to, so they needn't be searched separately.
+    pub fn distinct_moves(&self) -> Vec<GameMove> {
+        let symmetries = self.self_symmetries();
+        let mut distinct: Vec<GameMove> = Vec::new();
+        self.for_each_move(|game_move| {
+            if !symmetries
+                .iter()
+                .any(|&symmetry| distinct.contains(&game_move.transform(symmetry)))
+            {
+                distinct.push(game_move);
+            }
+        });
+        distinct
+    }
+
+    /// All symmetric variants of this position, in the order of `Symmetry::all()`.
+    pub fn symmetries(&self) -> Vec<(Symmetry, GameState)> {
+        Symmetry::all()
+            .iter()
+            .map(|&symmetry| (symmetry, self.transform(symmetry)))
+            .collect()
+    }
+
+    /// Whether no cell holds a piece.
+    pub fn is_board_empty(&self) -> bool {
+        self.board
+            .contents
+            .iter()
+            .flatten()
+            .all(|stack| stack.top() == 0)
+    }
+
+    /// Whether no piece can be placed or moved onto the stack at `position`: it
+    /// is topped by the largest size or as tall as `RuleSet::max_stack_height`.
+    pub fn is_stack_full(&self, position: Position) -> bool {
+        let stack = self.board.stack(position);
+        stack.top() == NUM_SIZES || stack.height() >= self.rules.max_stack_height
+    }
+
+    /// Whether every stack is full (see `is_stack_full`), so nothing more can be
+    /// placed or moved onto the board.
+    pub fn is_board_full(&self) -> bool {
+        (0..BOARD_ROWS).all(|row| (0..BOARD_COLS).all(|col| self.is_stack_full((row, col))))
+    }
+
+    /// How many cells are topped by White and by Black, in that order.
+    pub fn top_counts(&self) -> (usize, usize) {
+        let colors = self.top_colors();
+        let count = |target| {
+            colors
+                .iter()
+                .flatten()
+                .filter(|&&color| color == target)
+                .count()
+        };
+        (count(Color::White), count(Color::Black))
+    }
+
+    /// How many stacks `player` tops with a piece sitting directly on one of the
+    /// opponent's.
+    pub fn gobble_count(&self, player: Player) -> usize {
+        self.board
+            .contents
+            .iter()
+            .flatten()
+            .filter(|stack| {
+                stack.top_color() == player.color()
+                    && stack.color_below_top() == player.other().color()
+            })
+            .count()
+    }
+
+    /// Whether completing a diagonal wins: the rules must not disable diagonals,
+    /// and a diagonal of `RuleSet::win_length` cells must fit on the board.
+    pub fn has_diagonal_wins(&self) -> bool {
+        let win_length = self.rules.win_length;
+        self.rules.diagonal_wins && win_length <= BOARD_ROWS && win_length <= BOARD_COLS
+    }
+
+    /// Every run of cells that wins when one color tops all of it: rows, then
+    /// columns, then diagonals.
+    fn win_segments(&self) -> impl Iterator<Item = Segment> {
+        win_segments(
+            BOARD_ROWS,
+            BOARD_COLS,
+            self.rules.win_length,
+            self.rules.diagonal_wins,
+        )
+    }
+
+    /// Which cells lie on a diagonal that wins under the rules, or `None` if
+    /// diagonals are off.
+    fn diagonal_win_cells(&self) -> Option<&'static [[bool; BOARD_COLS]; BOARD_ROWS]> {
+        self.rules
+            .diagonal_wins
+            .then(|| &DIAGONAL_WIN_CELLS[self.rules.win_length - 1])
+    }
+
+    /// The player who completed a line, if the game is over. As in `raw_score`, only
+    /// the player who just moved is checked.
+    pub fn winner(&self) -> Option<Player> {
+        self.line_winner(&self.top_colors())
+    }
+
+    fn line_winner(&self, top_colors: &[[Color; BOARD_COLS]; BOARD_ROWS]) -> Option<Player> {
+        if self.last_moves[0] == Some(GameMove::Pass) {
+            return None;
+        }
+        let check_winner = self.turn.other();
+        let owned = |(row, col): Position| top_colors[row][col] == check_winner.color();
+        let won = self
+            .win_segments()
+            .any(|segment| segment.cells().all(owned));
+        won.then_some(check_winner)
+    }
+
+    /// The winner and the cells of the line they completed, the first in the order
+    /// rows, columns, diagonals if they completed several.
+    pub fn winning_line(&self) -> Option<(Player, Vec<Position>)> {
+        let winner = self.winner()?;
+        let top_colors = self.top_colors();
+        self.win_lines()
+            .into_iter()
+            .find(|line| {
+                line.iter()
+                    .all(|&(row, col)| top_colors[row][col] == winner.color())
+            })
+            .map(|line| (winner, line))
+    }
+
+    /// The cells of every line that wins when one color tops all of it.
+    fn win_lines(&self) -> Vec<Vec<Position>> {
+        self.win_segments()
+            .map(|segment| segment.cells().collect())
+            .collect()
+    }
+
+    /// For each cell, which colors it is part of an open line for: a line topped by
+    /// that color in every cell but one, where the remaining cell can still be covered.
+    pub fn threat_overlay(&self) -> [[ThreatInfo; BOARD_COLS]; BOARD_ROWS] {
+        let mut overlay = [[ThreatInfo::default(); BOARD_COLS]; BOARD_ROWS];
+        for (color, line) in self.open_lines() {
+            for (row, col) in line {
+                let info = &mut overlay[row][col];
+                if color == Color::White {
+                    info.white = true;
+                } else {
+                    info.black = true;
+                }
+            }
+        }
+        overlay
+    }
+
+    /// The open lines for `color`, as described for `threat_overlay`: the lines it
+    /// tops in every cell but one playable gap. Lines are `RuleSet::win_length`
+    /// cells long, or a whole row or column if that is shorter.
+    pub fn threatened_lines(&self, color: Color) -> Vec<Vec<Position>> {
+        self.open_lines()
+            .into_iter()
+            .filter(|(line_color, _)| *line_color == color)
+            .map(|(_, line)| line)
+            .collect()
+    }
+
+    /// Every open line, as described for `threat_overlay`, with the color it is open
+    /// for.
+    fn open_lines(&self) -> Vec<(Color, Vec<Position>)> {
+        let top_colors = self.top_colors();
+        let mut open = Vec::new();
+        for line in self.win_lines() {
+            for color in [Color::White, Color::Black] {
+                let missing: Vec<Position> = line
+                    .iter()
+                    .copied()
+                    .filter(|&(row, col)| top_colors[row][col] != color)
+                    .collect();
+                let [(row, col)] = missing[..] else {
+                    continue;
+                };
+                if self.is_stack_full((row, col)) {
+                    continue;
+                }
+                open.push((color, line.clone()));
+            }
+        }
+        open
+    }
+
+    /// The legal moves that win, or that change how many open lines either color
+    /// has (see `threat_overlay`), leaving out quiet moves. A basis for extending
+    /// the search along tactical lines.
+    pub fn critical_moves(&self) -> impl Iterator<Item = GameMove> {
+        let threats = |state: &GameState| {
+            let open = state.open_lines();
+            let white = open
+                .iter()
+                .filter(|(color, _)| *color == Color::White)
+                .count();
+            (white, open.len() - white)
+        };
+        let before = threats(self);
+        let mut scratch = self.clone();
+        let mut critical = Vec::new();
+        for game_move in self.legal_moves() {
+            scratch.make_move(game_move);
+            if scratch.winner().is_some() || threats(&scratch) != before {
+                critical.push(game_move);
+            }
+            scratch.unmake_move(game_move);
+        }
+        critical.into_iter()
+    }
+
+    /// The static evaluation under the default weights.
+    pub fn raw_score(&self) -> Score {
+        self.raw_score_with(&EvalWeights::default())
+    }
+
+    pub fn raw_score_with(&self, weights: &EvalWeights) -> Score {
+        let top_colors = self.top_colors();
+        if let Some(winner) = self.line_winner(&top_colors) {
+            return Score::for_player(winner);
+        }
+
+        let score = self
+            .positional_score(&top_colors, weights)
+            .saturating_add(self.pinned_score(&top_colors, weights));
+        Score::balanced(score)
+    }
+
+    /// `raw_score_with` under the cache's weights, looking the positional term up in
+    /// `cache` before computing it.
+    pub fn raw_score_cached(&self, cache: &mut EvalCache) -> Score {
+        let top_colors = self.top_colors();
+        if let Some(winner) = self.line_winner(&top_colors) {
+            return Score::for_player(winner);
+        }
+        // The top colors, two bits per cell, and whether diagonals count: all the
+        // positional term depends on.
+        let key = top_colors
+            .iter()
+            .flatten()
+            .fold(u64::from(self.has_diagonal_wins()), |key, &color| {
+                key << 2 | color as u64
+            });
+        let slot = (key % cache.slots.len() as u64) as usize;
+        let positional = match cache.slots[slot] {
+            Some((cached_key, score)) if cached_key == key => score,
+            _ => {
+                let score = self.positional_score(&top_colors, &cache.weights);
+                cache.slots[slot] = Some((key, score));
+                score
+            }
+        };
+        let pinned = self.pinned_score(&top_colors, &cache.weights);
+        Score::balanced(positional.saturating_add(pinned))
+    }
+
+    /// The pinned-piece penalties of both colors, White's counted negative.
+    fn pinned_score(
+        &self,
+        top_colors: &[[Color; BOARD_COLS]; BOARD_ROWS],
+        weights: &EvalWeights,
+    ) -> i32 {
+        if weights.pinned_piece == 0 {
+            return 0;
+        }
+        let mut score: i32 = 0;
+        for row in 0..BOARD_ROWS {
+            for col in 0..BOARD_COLS {
+                let stack = &self.board.contents[row][col];
+                let covered = stack.color_below_top();
+                if covered == Color::Empty || covered == stack.top_color() {
+                    continue;
+                }
+                let owned = |(line_row, line_col): Position| {
+                    (line_row, line_col) == (row, col) || top_colors[line_row][line_col] == covered
+                };
+                let completes_line = self.win_segments().any(|segment| {
+                    segment.cells().any(|cell| cell == (row, col)) && segment.cells().all(owned)
+                });
+                if completes_line {
+                    // The pinned piece belongs to the opponent of `covered`.
+                    let penalty = if covered == Color::White {
+                        weights.pinned_piece
+                    } else {
+                        weights.pinned_piece.saturating_neg()
+                    };
+                    score = score.saturating_add(penalty);
+                }
+            }
+        }
+        score
+    }
+
+    /// The sum of the weights of the cells each color tops, White's counted positive.
+    fn positional_score(
+        &self,
+        top_colors: &[[Color; BOARD_COLS]; BOARD_ROWS],
+        weights: &EvalWeights,
+    ) -> i32 {
+        let mut score: i32 = 0;
+        let diagonal_cells = self.diagonal_win_cells();
+
+        for (row, colors) in top_colors.iter().enumerate() {
+            for (col, &color) in colors.iter().enumerate() {
+                if color == Color::Empty {
+                    continue;
+                }
+                let base_score = if diagonal_cells.is_some_and(|cells| cells[row][col]) {
+                    weights.diagonal_cell
+                } else {
+                    weights.cell
+                };
+                let score_multiplier = if color == Color::White { 1 } else { -1 };
+                score = score.saturating_add(base_score.saturating_mul(score_multiplier));
+            }
+        }
+
+        score
+    }
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        GameState::new()
+    }
+}
+
+/// Every run of cells that wins on a `rows` by `cols` board when one color tops
+/// all of it: rows, then columns, then diagonals. Diagonals only count when
+/// `diagonal_wins` is on and the board fits one `win_length` cells long.
+fn win_segments(
+    rows: usize,
+    cols: usize,
+    win_length: usize,
+    diagonal_wins: bool,
+) -> impl Iterator<Item = Segment> {
+    let row_length = win_length.min(cols);
+    let col_length = win_length.min(rows);
+    let row_segments = (0..rows).flat_map(move |row| {
+        (0..=cols - row_length).map(move |col| Segment {
+            start: (row, col),
+            step: (0, 1),
+            len: row_length,
+        })
+    });
+    let col_segments = (0..cols).flat_map(move |col| {
+        (0..=rows - col_length).map(move |row| Segment {
+            start: (row, col),
+            step: (1, 0),
+            len: col_length,
+        })
+    });
+    let diagonal_rows = if diagonal_wins && win_length <= rows && win_length <= cols {
+        rows - win_length + 1
+    } else {
+        0
+    };
+    let diagonals = (0..diagonal_rows).flat_map(move |row| {
+        (0..=cols - win_length).flat_map(move |col| {
+            [
+                Segment {
+                    start: (row, col),
+                    step: (1, 1),
+                    len: win_length,
+                },
+                Segment {
+                    start: (row, cols - col - 1),
+                    step: (1, -1),
+                    len: win_length,
+                },
+            ]
+        })
+    });
+    row_segments.chain(col_segments).chain(diagonals)
+}
+
+/// Indexed by `win_length - 1`, the cells on some diagonal of `win_segments` when
+/// diagonals win, so evaluation doesn't walk the segments for every position.
+static DIAGONAL_WIN_CELLS: LazyLock<Vec<[[bool; BOARD_COLS]; BOARD_ROWS]>> = LazyLock::new(|| {
+    (1..=BOARD_ROWS.max(BOARD_COLS))
+        .map(|win_length| {
+            let mut cells = [[false; BOARD_COLS]; BOARD_ROWS];
+            for segment in win_segments(BOARD_ROWS, BOARD_COLS, win_length, true)
+                .filter(|segment| segment.step.0 != 0 && segment.step.1 != 0)
+            {
+                for (row, col) in segment.cells() {
+                    cells[row][col] = true;
+                }
+            }
+            cells
+        })
+        .collect()
+});
+
+/// A run of `len` cells from `start`, each `step` (rows, columns) from the last.
+#[derive(Clone, Copy)]
+struct Segment {
+    start: Position,
+    step: (usize, isize),
+    len: usize,
+}
+
+impl Segment {
+    fn cells(self) -> impl Iterator<Item = Position> {
+        let (row, col) = self.start;
+        let (row_step, col_step) = self.step;
+        (0..self.len).map(move |i| {
+            (
+                row + i * row_step,
+                col.wrapping_add_signed(i as isize * col_step),
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PositionError {
+    RowCount(usize),
+    CellCount {
+        row: usize,
+        count: usize,
+    },
+    InvalidPiece(String),
+    /// Pieces in a stack must be listed smallest first, one of each size at most.
+    StackOrder(Position),
+    /// More pieces of this color and size are on the board than the stock allows.
+    TooManyPieces {
+        player: Player,
+        size: usize,
+    },
+}
+
+impl Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PositionError::RowCount(count) => {
+                write!(f, "expected {BOARD_ROWS} rows, found {count}")
+            }
+            PositionError::CellCount { row, count } => {
+                write!(
+                    f,
+                    "expected {BOARD_COLS} cells in row {}, found {count}",
+                    row + 1
+                )
+            }
+            PositionError::InvalidPiece(piece) => write!(f, "invalid piece {piece:?}"),
+            PositionError::StackOrder(position) => {
+                write!(f, "stack at {} is out of order", format_position(*position))
+            }
+            PositionError::TooManyPieces { player, size } => {
+                write!(f, "too many {player:?} pieces of size {size}")
+            }
+        }
+    }
+}
+
+impl Error for PositionError {}
+
+/// A board coordinate as `(row, col)`.
+pub type Position = (usize, usize);
+
+/// Cells are written chess-style: a column letter followed by a one-based row number,
+/// so `(0, 0)` is `a1` and `(3, 2)` is `c4`.
+pub fn format_position((row, col): Position) -> String {
+    format!("{}{}", (b'a' + col as u8) as char, row + 1)
+}
+
+pub fn parse_position(text: &str) -> Option<Position> {
+    let mut chars = text.chars();
+    let col = chars.next()?.to_ascii_lowercase();
+    let row: usize = chars.as_str().parse().ok()?;
+    if !('a'..).take(BOARD_COLS).any(|c| c == col) || row == 0 || row > BOARD_ROWS {
+        return None;
+    }
+    Some((row - 1, col as usize - 'a' as usize))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameMove {
+    Place {
+        size: usize,
+        dest: (usize, usize),
+    },
+    Move {
+        source: (usize, usize),
+        dest: (usize, usize),
+    },
+    /// Only legal under `RuleSet::allow_pass`.
+    Pass,
+}
+
+impl GameMove {
+    /// Where the piece is dropped, or `None` for a pass.
+    pub fn dest(&self) -> Option<Position> {
+        match *self {
+            GameMove::Place { dest, .. } | GameMove::Move { dest, .. } => Some(dest),
+            GameMove::Pass => None,
+        }
+    }
+
+    /// Where the piece is lifted from, or `None` for a placement from the reserve
+    /// or a pass.
+    pub fn source(&self) -> Option<Position> {
+        match *self {
+            GameMove::Place { .. } | GameMove::Pass => None,
+            GameMove::Move { source, .. } => Some(source),
+        }
+    }
+
+    /// The size of the piece being played, looking at `board` as it was before the
+    /// move, or `None` for a pass.
+    pub fn size(&self, board: &Board) -> Option<usize> {
+        match *self {
+            GameMove::Place { size, .. } => Some(size),
+            GameMove::Move {
+                source: (row, col), ..
+            } => Some(board.contents[row][col].top() - 1),
+            GameMove::Pass => None,
+        }
+    }
+
+    /// The same move on a board transformed by `symmetry`.
+    pub fn transform(self, symmetry: Symmetry) -> GameMove {
+        match self {
+            GameMove::Place { size, dest } => GameMove::Place {
+                size,
+                dest: symmetry.apply(dest),
+            },
+            GameMove::Move { source, dest } => GameMove::Move {
+                source: symmetry.apply(source),
+                dest: symmetry.apply(dest),
+            },
+            GameMove::Pass => GameMove::Pass,
+        }
+    }
+}
+
+/// A canonical order for sorting move lists: placements first, by size and then
+/// destination; then relocations, by destination and then source; then the pass.
+/// Positions compare by row, then column.
+impl Ord for GameMove {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let key = |game_move: &GameMove| match *game_move {
+            GameMove::Place { size, dest } => (0, size, dest, (0, 0)),
+            GameMove::Move { source, dest } => (1, 0, dest, source),
+            GameMove::Pass => (2, 0, (0, 0), (0, 0)),
+        };
+        key(self).cmp(&key(other))
+    }
+}
+
+impl PartialOrd for GameMove {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// One of the ways to rotate or reflect the board onto itself. Rotations by a
+/// quarter turn and reflections across a diagonal only exist on square boards.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Symmetry {
+    Identity,
+    /// A quarter turn clockwise, with row 1 at the bottom.
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Swaps the columns, `a` with the last column.
+    ReflectHorizontal,
+    /// Swaps the rows, `1` with the last row.
+    ReflectVertical,
+    /// Across the diagonal through `a1`.
+    ReflectDiagonal,
+    /// Across the other diagonal.
+    ReflectAntiDiagonal,
+}
+
+impl Symmetry {
+    /// The symmetries of the board, all eight on a square board and four otherwise.
+    pub fn all() -> &'static [Symmetry] {
+        const ALL: [Symmetry; 8] = [
+            Symmetry::Identity,
+            Symmetry::Rotate180,
+            Symmetry::ReflectHorizontal,
+            Symmetry::ReflectVertical,
+            Symmetry::Rotate90,
+            Symmetry::Rotate270,
+            Symmetry::ReflectDiagonal,
+            Symmetry::ReflectAntiDiagonal,
+        ];
+        if SQUARE_BOARD {
+            &ALL
+        } else {
+            &ALL[..4]
+        }
+    }
+
+    /// Where the cell at `position` ends up.
+    pub fn apply(self, (row, col): Position) -> Position {
+        let (last_row, last_col) = (BOARD_ROWS - 1, BOARD_COLS - 1);
+        match self {
+            Symmetry::Identity => (row, col),
+            Symmetry::Rotate90 => (last_col - col, row),
+            Symmetry::Rotate180 => (last_row - row, last_col - col),
+            Symmetry::Rotate270 => (col, last_row - row),
+            Symmetry::ReflectHorizontal => (row, last_col - col),
+            Symmetry::ReflectVertical => (last_row - row, col),
+            Symmetry::ReflectDiagonal => (col, row),
+            Symmetry::ReflectAntiDiagonal => (last_col - col, last_row - row),
+        }
+    }
+}
+
+/// Notation: a placement is `<size>@<cell>` (e.g. `3@b2`), a relocation is
+/// `<source>-<dest>` (e.g. `a1-b2`) and a pass is `--`.
+impl Display for GameMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            GameMove::Place { size, dest } => write!(f, "{size}@{}", format_position(dest)),
+            GameMove::Move { source, dest } => {
+                write!(f, "{}-{}", format_position(source), format_position(dest))
+            }
+            GameMove::Pass => write!(f, "--"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotationError(String);
+
+impl Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid move notation: {:?}", self.0)
+    }
+}
+
+impl Error for NotationError {}
+
+/// Why a move is illegal in a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    /// A position is off the board or the size doesn't exist.
+    OutOfBounds(GameMove),
+    /// The destination is topped by the largest size or as tall as
+    /// `RuleSet::max_stack_height` allows.
+    DestinationFull(Position),
+    /// The piece must be at least size `needed` to cover the destination.
+    PieceTooSmall {
+        needed: usize,
+        have: usize,
+    },
+    NoReserve {
+        player: Player,
+        size: usize,
+    },
+    EmptySource(Position),
+    NotYourPiece(Position),
+    SamePosition(Position),
+    /// Forbidden by `RuleSet::forbid_reversal`.
+    ReversesLastMove,
+    /// A reserve piece placed on an occupied cell while
+    /// `RuleSet::reserve_can_gobble` is off.
+    ReserveCannotGobble(Position),
+    /// This player has already won.
+    GameOver(Player),
+    /// A pass while `RuleSet::allow_pass` is off, or straight after the
+    /// opponent's pass.
+    PassNotAllowed,
+    /// A size left out of `RuleSet::usable_sizes`, placed, or moved while
+    /// `RuleSet::move_unusable_sizes` is off.
+    SizeNotInPlay(usize),
+    /// A move that gobbles none of the opponent's pieces while one that does is
+    /// available and `RuleSet::mandatory_capture` is on.
+    CaptureRequired,
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MoveError::OutOfBounds(game_move) => write!(f, "{game_move:?} is off the board"),
+            MoveError::DestinationFull(dest) => {
+                write!(f, "the stack at {} is full", format_position(dest))
+            }
+            MoveError::PieceTooSmall { needed, have } => write!(
+                f,
+                "a size {have} piece can't cover that cell; it needs size {needed} or larger"
+            ),
+            MoveError::NoReserve { player, size } => {
+                write!(f, "{player:?} has no size {size} pieces left in reserve")
+            }
+            MoveError::EmptySource(source) => {
+                write!(f, "there is no piece at {}", format_position(source))
+            }
+            MoveError::NotYourPiece(source) => {
+                write!(
+                    f,
+                    "the piece at {} belongs to the opponent",
+                    format_position(source)
+                )
+            }
+            MoveError::SamePosition(source) => {
+                write!(
+                    f,
+                    "the piece at {} must move somewhere else",
+                    format_position(source)
+                )
+            }
+            MoveError::ReversesLastMove => {
+                write!(
+                    f,
+                    "a piece can't be moved straight back to where it came from"
+                )
+            }
+            MoveError::ReserveCannotGobble(dest) => write!(
+                f,
+                "pieces from the reserve can only be placed on empty cells, and {} is occupied",
+                format_position(dest)
+            ),
+            MoveError::GameOver(winner) => write!(f, "the game is over; {winner:?} has won"),
+            MoveError::PassNotAllowed => write!(f, "passing is not allowed here"),
+            MoveError::SizeNotInPlay(size) => write!(f, "size {size} pieces are not in play"),
+            MoveError::CaptureRequired => {
+                write!(
+                    f,
+                    "an opposing piece can be gobbled, so the move must gobble one"
+                )
+            }
+        }
+    }
+}
+
+impl Error for MoveError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyError {
+    Notation(NotationError),
+    /// The move parsed but is not legal in the position.
+    Illegal(MoveError),
+}
+
+impl Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::Notation(error) => error.fmt(f),
+            ApplyError::Illegal(error) => write!(f, "illegal move: {error}"),
+        }
+    }
+}
+
+impl Error for ApplyError {}
+
+impl FromStr for GameMove {
+    type Err = NotationError;
+
+    fn from_str(text: &str) -> Result<GameMove, NotationError> {
+        let error = || NotationError(text.to_string());
+        let text = text.trim();
+        if text == "--" {
+            Ok(GameMove::Pass)
+        } else if let Some((size, dest)) = text.split_once('@') {
+            let size: usize = size.parse().map_err(|_| error())?;
+            if size >= NUM_SIZES {
+                return Err(error());
+            }
+            let dest = parse_position(dest).ok_or_else(error)?;
+            Ok(GameMove::Place { size, dest })
+        } else if let Some((source, dest)) = text.split_once('-') {
+            let source = parse_position(source).ok_or_else(error)?;
+            let dest = parse_position(dest).ok_or_else(error)?;
+            Ok(GameMove::Move { source, dest })
+        } else {
+            Err(error())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Opening,
+    Midgame,
+    Endgame,
+}
+
+/// A cell's part in open lines, as reported by `GameState::threat_overlay`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ThreatInfo {
+    /// The cell is on a line White is one cell away from completing.
+    pub white: bool,
+    /// The cell is on a line Black is one cell away from completing.
+    pub black: bool,
+}
+
+/// The number of pieces on the board at which each phase begins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhaseThresholds {
+    pub midgame: usize,
+    pub endgame: usize,
+}
+
+impl Default for PhaseThresholds {
+    fn default() -> Self {
+        PhaseThresholds {
+            midgame: 6,
+            endgame: 16,
+        }
+    }
+}
+
+/// The terms of `GameState::raw_score_with`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EvalWeights {
+    /// Points for each cell a color tops.
+    pub cell: i32,
+    /// Used instead of `cell` for cells on a diagonal, when diagonals are win lines.
+    pub diagonal_cell: i32,
+    /// Penalty for each pinned piece: one covering an opponent's piece that would
+    /// complete a line for the opponent if uncovered, so it can't safely be moved.
+    /// Off by default, since finding pins costs more than the rest of the evaluation.
+    pub pinned_piece: i32,
+}
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        EvalWeights {
+            cell: 2,
+            diagonal_cell: 3,
+            pinned_piece: 0,
+        }
+    }
+}
+
+/// Scores positions where the search stops. Won and repeated positions never reach
+/// the evaluator; the search scores those itself.
+pub trait Evaluator {
+    /// The value of `state` from White's perspective.
+    fn evaluate(&self, state: &GameState) -> Score;
+}
+
+/// The built-in heuristic, `GameState::raw_score_with`.
+impl Evaluator for EvalWeights {
+    fn evaluate(&self, state: &GameState) -> Score {
+        state.raw_score_with(self)
+    }
+}
+
+/// Memoizes the positional term of the evaluation for `GameState::raw_score_cached`.
+/// The term only depends on which color tops each cell, so positions that differ
+/// only in covered pieces share an entry. Entries are keyed by the exact top-color
+/// pattern, so a lookup never returns another pattern's score.
+pub struct EvalCache {
+    weights: EvalWeights,
+    slots: Vec<Option<(u64, i32)>>,
+}
+
+const _: () = assert!(
+    BOARD_ROWS * BOARD_COLS * 2 < 64,
+    "the top-color pattern doesn't fit in an EvalCache key"
+);
+
+impl EvalCache {
+    /// A cache with room for `size` entries (at least one), scoring with `weights`.
+    pub fn new(size: usize, weights: EvalWeights) -> EvalCache {
+        EvalCache {
+            weights,
+            slots: vec![None; size.max(1)],
+        }
+    }
+
+    pub fn weights(&self) -> &EvalWeights {
+        &self.weights
+    }
+}
+
+/// Optional filters applied by `GameState::legal_moves_with`.
+#[derive(Clone, Debug, Default)]
+pub struct MoveOptions {
+    /// Leave out relocations that don't change the top color of any cell.
+    pub exclude_shuffles: bool,
+    /// On empty cells, only place the smallest size left in reserve, keeping the
+    /// larger pieces back.
+    pub smallest_on_empty: bool,
+    /// Leave out placements on a cell that one of the remaining relocations also
+    /// reaches without uncovering an opponent's piece, saving the reserve piece.
+    /// The relocation still empties or changes its source cell, so this can drop
+    /// the better move.
+    pub prefer_relocation: bool,
+}
+
+/// Ranks moves for `GameState::ordered_moves`, so that the ones most likely to be
+/// good come first.
+pub trait MoveOrdering {
+    /// How promising `game_move` is in `state`; higher comes first.
+    fn priority(&self, state: &GameState, game_move: GameMove) -> i32;
+}
+
+/// Moves that cover an opponent's piece first, larger pieces before smaller, then
+/// moves closer to the center of the board.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CoverFirst;
+
+impl MoveOrdering for CoverFirst {
+    fn priority(&self, state: &GameState, game_move: GameMove) -> i32 {
+        // Passes come last.
+        let Some(dest) = game_move.dest() else {
+            return i32::MIN;
+        };
+        let stack = state.board.stack(dest);
+        let cover = if stack.top_color() == state.turn.other().color() {
+            stack.top() as i32 * (BOARD_ROWS + BOARD_COLS) as i32
+        } else {
+            0
+        };
+        cover - distance_from_center(dest) as i32
+    }
+}
+
+/// Twice the Manhattan distance from `position` to the center of the board, so
+/// that it is a whole number on boards of either parity.
+fn distance_from_center((row, col): Position) -> usize {
+    (2 * row).abs_diff(BOARD_ROWS - 1) + (2 * col).abs_diff(BOARD_COLS - 1)
+}
+
+/// Where a piece being moved is picked up from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Source {
+    Reserve(usize),
+    Board(Position),
+}
+
+/// A move halfway through being made: the piece has been picked up but not yet
+/// dropped. Mirrors drag-and-drop front-ends, which need the legal destinations
+/// for the held piece before the player chooses one.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PendingMove {
+    source: Source,
+    destinations: Vec<Position>,
+}
+
+impl PendingMove {
+    /// Pick up a piece in `state`. Returns `None` if the piece cannot be played anywhere,
+    /// e.g. it belongs to the opponent or the reserve is empty.
+    pub fn lift(state: &GameState, source: Source) -> Option<PendingMove> {
+        let moves: Vec<GameMove> = match source {
+            Source::Reserve(size) => state.moves_from_reserve(size).collect(),
+            Source::Board(position) => state.moves_from_board(position).collect(),
+        };
+        let destinations: Vec<Position> = moves.iter().filter_map(GameMove::dest).collect();
+        if destinations.is_empty() {
+            return None;
+        }
+        Some(PendingMove {
+            source,
+            destinations,
+        })
+    }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    pub fn destinations(&self) -> &[Position] {
+        &self.destinations
+    }
+
+    /// Finish the move by dropping the held piece on `dest`, if that is legal.
+    pub fn drop_at(&self, dest: Position) -> Option<GameMove> {
+        if !self.destinations.contains(&dest) {
+            return None;
+        }
+        Some(match self.source {
+            Source::Reserve(size) => GameMove::Place { size, dest },
+            Source::Board(source) => GameMove::Move { source, dest },
+        })
+    }
+}
+
+/// How a game that can't usefully continue ends.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameResult {
+    Win(Player),
+    Draw,
+}
+
+impl GameResult {
+    pub fn score(self) -> Score {
+        match self {
+            GameResult::Win(player) => Score::for_player(player),
+            GameResult::Draw => Score::Balanced(0),
+        }
+    }
+}
+
+/// Scores are always from White's point of view, whoever is to move: `WhiteFavored`
+/// is a forced win for White, and a larger `Balanced` value is better for White.
+/// A forced win holds the number of plies until the winning line is completed, 0
+/// once it is on the board. The sooner a win comes, the better it is for the winner,
+/// so the winner hurries and the loser holds out as long as possible.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Score {
+    WhiteFavored(u32),
+    BlackFavored(u32),
+    Balanced(i32),
+}
+
+impl Score {
+    /// The best score for White: White has already won.
+    pub const MAX: Score = Score::WhiteFavored(0);
+    /// The best score for Black: Black has already won.
+    pub const MIN: Score = Score::BlackFavored(0);
+
+    /// Forced wins further off than this many plies all count as this far off.
+    pub const MAX_WIN_PLIES: u32 = 1 << 16;
+
+    /// The largest magnitude a `Balanced` score can have. Evaluation terms are added
+    /// with saturating arithmetic and clamped to this range, leaving the values from
+    /// `i32::MAX - MAX_WIN_PLIES` up (and their negations down) free for decisive
+    /// results when a score is flattened into a single number.
+    pub const MAX_BALANCED: i32 = i32::MAX - Score::MAX_WIN_PLIES as i32 - 1;
+
+    /// A balanced score, clamped to `-MAX_BALANCED..=MAX_BALANCED`.
+    pub fn balanced(score: i32) -> Score {
+        Score::Balanced(score.clamp(-Score::MAX_BALANCED, Score::MAX_BALANCED))
+    }
+
+    /// A win for `player` that is already on the board.
+    pub fn for_player(player: Player) -> Score {
+        if player == Player::White {
+            Score::WhiteFavored(0)
+        } else {
+            Score::BlackFavored(0)
+        }
+    }
+
+    /// Whether the score is a forced win for either side.
+    pub fn is_decisive(&self) -> bool {
+        self.winner().is_some()
+    }
+
+    pub fn is_win_for(&self, player: Player) -> bool {
+        self.winner() == Some(player)
+    }
+
+    pub fn winner(&self) -> Option<Player> {
+        match self {
+            Score::WhiteFavored(_) => Some(Player::White),
+            Score::BlackFavored(_) => Some(Player::Black),
+            Score::Balanced(_) => None,
+        }
+    }
+
+    /// How many plies away a forced win is, or `None` if the score isn't decisive.
+    pub fn plies_to_win(&self) -> Option<u32> {
+        match *self {
+            Score::WhiteFavored(plies) | Score::BlackFavored(plies) => Some(plies),
+            Score::Balanced(_) => None,
+        }
+    }
+
+    /// The score as a single number, ordered like the score itself: balanced
+    /// scores as they are, a forced win for White as `i32::MAX` less the plies to
+    /// it, and a forced win for Black as `i32::MIN` plus them. Distances are capped
+    /// at `MAX_WIN_PLIES`.
+    pub fn white_perspective(&self) -> i32 {
+        match *self {
+            Score::WhiteFavored(plies) => i32::MAX - plies.min(Score::MAX_WIN_PLIES) as i32,
+            Score::BlackFavored(plies) => i32::MIN + plies.min(Score::MAX_WIN_PLIES) as i32,
+            Score::Balanced(score) => score,
+        }
+    }
+
+    /// A child's score as its parent sees it: a forced win is one ply further off.
+    fn backed_up(self) -> Score {
+        let further = |plies: u32| (plies + 1).min(Score::MAX_WIN_PLIES);
+        match self {
+            Score::WhiteFavored(plies) => Score::WhiteFavored(further(plies)),
+            Score::BlackFavored(plies) => Score::BlackFavored(further(plies)),
+            balanced => balanced,
+        }
+    }
+
+    /// A parent's search bound as its child sees it, the inverse of `backed_up`. A
+    /// bound on a win already on the board has no inverse and stays as it is, which
+    /// only widens the child's window.
+    fn for_child(self) -> Score {
+        match self {
+            Score::WhiteFavored(plies) => Score::WhiteFavored(plies.saturating_sub(1)),
+            Score::BlackFavored(plies) => Score::BlackFavored(plies.saturating_sub(1)),
+            balanced => balanced,
+        }
+    }
+
+    /// The score mapped into `-1.0..=1.0` for evaluation bars: balanced scores
+    /// linearly, reaching the ends at `scale` points (at least one) for either
+    /// side, and forced wins at the ends. Never decreases as the score increases.
+    pub fn to_eval_units(&self, scale: i32) -> f32 {
+        match *self {
+            Score::WhiteFavored(_) => 1.0,
+            Score::BlackFavored(_) => -1.0,
+            Score::Balanced(score) => (score as f32 / scale.max(1) as f32).clamp(-1.0, 1.0),
+        }
+    }
+
+    /// A balanced score moved by `delta` points, clamped like `balanced`. Forced
+    /// wins are unchanged.
+    fn shifted(self, delta: i32) -> Score {
+        match self {
+            Score::Balanced(score) => Score::balanced(score.saturating_add(delta)),
+            decisive => decisive,
+        }
+    }
+
+    /// The next lower score, or `MIN` if there is none. Forced wins are taken to be
+    /// at most `MAX_WIN_PLIES` plies off.
+    fn just_below(self) -> Score {
+        match self {
+            Score::WhiteFavored(plies) if plies < Score::MAX_WIN_PLIES => {
+                Score::WhiteFavored(plies + 1)
+            }
+            Score::WhiteFavored(_) => Score::Balanced(Score::MAX_BALANCED),
+            Score::Balanced(score) if score > -Score::MAX_BALANCED => Score::Balanced(score - 1),
+            Score::Balanced(_) => Score::BlackFavored(Score::MAX_WIN_PLIES),
+            Score::BlackFavored(plies) => Score::BlackFavored(plies.saturating_sub(1)),
+        }
+    }
+
+    /// The next higher score, or `MAX` if there is none. Forced wins are taken to be
+    /// at most `MAX_WIN_PLIES` plies off.
+    fn just_above(self) -> Score {
+        match self {
+            Score::BlackFavored(plies) if plies < Score::MAX_WIN_PLIES => {
+                Score::BlackFavored(plies + 1)
+            }
+            Score::BlackFavored(_) => Score::Balanced(-Score::MAX_BALANCED),
+            Score::Balanced(score) if score < Score::MAX_BALANCED => Score::Balanced(score + 1),
+            Score::Balanced(_) => Score::WhiteFavored(Score::MAX_WIN_PLIES),
+            Score::WhiteFavored(plies) => Score::WhiteFavored(plies.saturating_sub(1)),
+        }
+    }
+}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self == other {
+            return Ordering::Equal;
+        }
+        match (*self, *other) {
+            // The sooner a win, the better for the winner.
+            (Score::WhiteFavored(self_plies), Score::WhiteFavored(other_plies)) => {
+                other_plies.cmp(&self_plies)
+            }
+            (Score::BlackFavored(self_plies), Score::BlackFavored(other_plies)) => {
+                self_plies.cmp(&other_plies)
+            }
+            (Score::WhiteFavored(_), _) | (_, Score::BlackFavored(_)) => Ordering::Greater,
+            (Score::BlackFavored(_), _) | (_, Score::WhiteFavored(_)) => Ordering::Less,
+            (Score::Balanced(self_score), Score::Balanced(other_score)) => {
+                self_score.cmp(&other_score)
+            }
+        }
+    }
+}
+
+/// What `Node::search_streaming` knows after completing a depth.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IterationInfo {
+    pub depth: i32,
+    pub best_move: Option<GameMove>,
+    /// The value of the root, from White's perspective.
+    pub score: Score,
+    /// `best_move` followed by the expected replies.
+    pub pv: Vec<GameMove>,
+    /// Nodes in the tree, the root included. Resolved subtrees are dropped, so
+    /// this can shrink from one depth to the next.
+    pub nodes: u64,
+}
+
+/// Children are shared between clones of a tree and copied only when one of the
+/// clones changes them, so snapshotting a tree is cheap.
+#[derive(Clone)]
+pub enum NodeState {
+    GameState(Box<GameState>),
+    Branches(Vec<(GameMove, Arc<Node>)>),
+    Resolved,
+}
+
+#[derive(Clone)]
+pub struct Node {
+    pub score: Score,
+    pub turn: Player,
+    state: NodeState,
+    /// The first child move reaching `score`, kept after the node is resolved.
+    best_move: Option<GameMove>,
+}
+
+/// Remembers `GameState::raw_score` by Zobrist hash for `Node::branch_cached`,
+/// dropping the least recently used entry when full. The hash doesn't cover the
+/// rules, so a cache must only be shared by positions played under the same rules.
+pub struct ScoreCache {
+    capacity: usize,
+    /// Each score with the stamp of its latest use.
+    entries: HashMap<u64, (Score, u64)>,
+    /// Keys in order of use, oldest first. A key appears once per use; only the
+    /// occurrence whose stamp matches `entries` counts.
+    recency: VecDeque<(u64, u64)>,
+    next_stamp: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl ScoreCache {
+    /// A cache holding up to `capacity` scores (at least one).
+    pub fn new(capacity: usize) -> ScoreCache {
+        ScoreCache {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            recency: VecDeque::new(),
+            next_stamp: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// How many lookups found their score in the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// How many lookups had to compute their score.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// The static score of `game`, computed only if it isn't cached.
+    pub fn raw_score(&mut self, game: &GameState) -> Score {
+        let key = game.zobrist();
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        let score = match self.entries.get_mut(&key) {
+            Some((score, used)) => {
+                self.hits += 1;
+                *used = stamp;
+                *score
+            }
+            None => {
+                self.misses += 1;
+                let score = game.raw_score();
+                if self.entries.len() == self.capacity {
+                    self.evict();
+                }
+                self.entries.insert(key, (score, stamp));
+                score
+            }
+        };
+        self.recency.push_back((key, stamp));
+        if self.recency.len() > 2 * self.capacity {
+            let entries = &self.entries;
+            self.recency
+                .retain(|(key, stamp)| entries.get(key).is_some_and(|&(_, used)| used == *stamp));
+        }
+        score
+    }
+
+    /// Drop the least recently used entry.
+    fn evict(&mut self) {
+        while let Some((key, stamp)) = self.recency.pop_front() {
+            if self
+                .entries
+                .get(&key)
+                .is_some_and(|&(_, used)| used == stamp)
+            {
+                self.entries.remove(&key);
+                return;
+            }
+        }
+    }
+}
+
+impl Node {
+    pub fn new(game: GameState) -> Node {
+        Node {
+            score: game.raw_score(),
+            turn: game.turn,
+            state: NodeState::GameState(Box::new(game)),
+            best_move: None,
+        }
+    }
+
+    /// `new`, taking the score from `cache` when the position is in it.
+    pub fn new_cached(game: GameState, cache: &mut ScoreCache) -> Node {
+        Node {
+            score: cache.raw_score(&game),
+            turn: game.turn,
+            state: NodeState::GameState(Box::new(game)),
+            best_move: None,
+        }
+    }
+
+    /// The node's score as a single number for plotting, positive when White is
+    /// better regardless of whose turn it is. Forced wins map to `i32::MAX` for White
+    /// and `i32::MIN` for Black, offset toward zero by the plies until the win; see
+    /// `Score::white_perspective`.
+    pub fn white_perspective_score(&self) -> i32 {
+        self.score.white_perspective()
+    }
+
+    /// The move to the best child expanded so far, `None` before the node is
+    /// expanded or if it has no moves.
+    pub fn best_move(&self) -> Option<GameMove> {
+        self.best_move
+    }
+
+    /// The best move followed by the best replies, as far as the tree is expanded.
+    pub fn principal_variation(&self) -> Vec<GameMove> {
+        let mut pv = Vec::new();
+        let mut node = self;
+        while let Some(game_move) = node.best_move {
+            pv.push(game_move);
+            let NodeState::Branches(ref branches) = node.state else {
+                break;
+            };
+            match branches
+                .iter()
+                .find(|(branch_move, _)| *branch_move == game_move)
+            {
+                Some((_, branch)) => node = branch,
+                None => break,
+            }
+        }
+        pv
+    }
+
+    fn update_score(&mut self) {
+        if let NodeState::Branches(ref branches) = self.state {
+            let mut best: Option<(GameMove, Score)> = None;
+            for (game_move, branch) in branches {
+                let branch_score = branch.score.backed_up();
+                let improves = match best {
+                    None => true,
+                    Some((_, score)) if self.turn == Player::White => branch_score > score,
+                    Some((_, score)) => branch_score < score,
+                };
+                if improves {
+                    best = Some((*game_move, branch_score));
+                }
+            }
+            if let Some((game_move, score)) = best {
+                self.score = score;
+                self.best_move = Some(game_move);
+            }
+            if self.score.is_decisive() {
+                self.state = NodeState::Resolved;
+            }
+        }
+    }
+
+    /// Visit every node expanded below this one in pre-order, with the move leading to
+    /// it and its depth below this node (1 for the children). This node itself is not
+    /// visited, since no move leads to it.
+    pub fn walk(&self, visitor: &mut impl FnMut(&GameMove, &Node, u32)) {
+        self.walk_from(visitor, 1);
+    }
+
+    fn walk_from(&self, visitor: &mut impl FnMut(&GameMove, &Node, u32), depth: u32) {
+        if let NodeState::Branches(ref branches) = self.state {
+            for (game_move, branch) in branches {
+                visitor(game_move, branch, depth);
+                branch.walk_from(visitor, depth + 1);
+            }
+        }
+    }
+
+    /// Deepen the tree one ply at a time up to `max_depth`, calling `on_iteration`
+    /// after each depth with what is known so far, e.g. to update a live analysis
+    /// pane. Stops early once the result is decided.
+    pub fn search_streaming(
+        &mut self,
+        max_depth: i32,
+        mut on_iteration: impl FnMut(IterationInfo),
+    ) {
+        for depth in 1..=max_depth {
+            self.branch(depth);
+            let mut nodes = 1;
+            self.walk(&mut |_, _, _| nodes += 1);
+            on_iteration(IterationInfo {
+                depth,
+                best_move: self.best_move,
+                score: self.score,
+                pv: self.principal_variation(),
+                nodes,
+            });
+            if self.score.is_decisive() {
+                break;
+            }
+        }
+    }
+
+    /// Expand the tree below this node to `depth` plies and back up the scores.
+    ///
+    /// Moves are generated once, the first time a node is expanded; the node then
+    /// keeps its children instead of its game state, so later calls with a greater
+    /// depth (as in iterative deepening) only extend the existing children.
+    pub fn branch(&mut self, depth: i32) {
+        self.branch_with(depth, None);
+    }
+
+    /// `branch`, scoring new children through `cache`, so that positions reached
+    /// by several move orders are only scored once.
+    pub fn branch_cached(&mut self, depth: i32, cache: &mut ScoreCache) {
+        self.branch_with(depth, Some(cache));
+    }
+
+    fn branch_with(&mut self, depth: i32, mut cache: Option<&mut ScoreCache>) {
+        // A decided game has no meaningful continuation.
+        if self.score.is_decisive() {
+            self.state = NodeState::Resolved;
+            return;
+        }
+        match self.state {
+            NodeState::GameState(ref game_state) => {
+                let mut branches: Vec<(GameMove, Arc<Node>)> = game_state
+                    .branch()
+                    .into_iter()
+                    .map(|(branch_move, branch_state)| {
+                        let node = match cache.as_deref_mut() {
+                            Some(cache) => Node::new_cached(branch_state, cache),
+                            None => Node::new(branch_state),
+                        };
+                        (branch_move, Arc::new(node))
+                    })
+                    .collect();
+
+                if depth > 1 {
+                    for (_, branch) in &mut branches {
+                        Arc::make_mut(branch).branch_with(depth - 1, cache.as_deref_mut());
+                    }
+                }
+
+                self.state = NodeState::Branches(branches);
+                self.update_score();
+            }
+            NodeState::Branches(ref mut branches) => {
+                if depth == 1 {
+                    return;
+                }
+                for (_, branch) in branches {
+                    Arc::make_mut(branch).branch_with(depth - 1, cache.as_deref_mut());
+                }
+                self.update_score();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// A move-choosing AI. Object safe, so different engines can be stored as `Box<dyn Engine>`
+/// and plugged into the same driver.
+pub trait Engine {
+    fn choose_move(&mut self, state: &GameState) -> Option<GameMove>;
+    fn name(&self) -> &str;
+}
+
+/// Minimax value of `state` searched `depth` plies deep, with alpha-beta pruning.
+/// The result is exact whenever it lies strictly between `alpha` and `beta`;
+/// otherwise it is only a bound, and is stored in `tt` marked as such. An entry
+/// is only used at the depth it was searched to, so searching with a table gives
+/// the same value as searching without one (except that repetition draws depend
+/// on the path to a position, which the table doesn't know about).
+///
+/// Moves are made and unmade on `state` itself, so positions repeated anywhere in
+/// its history, including earlier in the search, are scored as draws.
+pub fn alpha_beta(
+    state: &mut GameState,
+    depth: i32,
+    alpha: Score,
+    beta: Score,
+    tt: Option<&mut TranspositionTable>,
+) -> Score {
+    alpha_beta_with(state, depth, alpha, beta, tt, &EngineConfig::default())
+}
+
+/// `alpha_beta` with the evaluation weights and move ordering taken from `config`.
+/// The depth and time limit in `config` are ignored.
+pub fn alpha_beta_with(
+    state: &mut GameState,
+    depth: i32,
+    alpha: Score,
+    beta: Score,
+    tt: Option<&mut TranspositionTable>,
+    config: &EngineConfig,
+) -> Score {
+    alpha_beta_counted(
+        state,
+        depth,
+        alpha,
+        beta,
+        tt,
+        config,
+        &config.weights,
+        None,
+        &mut SearchStats::default(),
+    )
+}
+
+/// `alpha_beta_with` scoring leaves with `evaluator` instead of `config.weights`,
+/// and adding the nodes it visits to `stats`. Gives up once `deadline` passes,
+/// setting `stats.timed_out` and returning a meaningless score.
+#[allow(clippy::too_many_arguments)]
+fn alpha_beta_counted(
+    state: &mut GameState,
+    depth: i32,
+    mut alpha: Score,
+    mut beta: Score,
+    mut tt: Option<&mut TranspositionTable>,
+    config: &EngineConfig,
+    evaluator: &dyn Evaluator,
+    deadline: Option<Instant>,
+    stats: &mut SearchStats,
+) -> Score {
+    stats.nodes += 1;
+    if out_of_time(deadline, stats) {
+        return Score::Balanced(0);
+    }
+    if let Some(score) = leaf_score(state, depth, evaluator) {
+        return score;
+    }
+
+    let key = tt.as_deref().map_or(0, |tt| tt.key(state));
+    let mut hash_move = None;
+    if let Some(entry) = tt.as_deref().and_then(|tt| tt.probe(key)) {
+        if entry.depth == depth {
+            stats.tt_hits += 1;
+            match entry.bound {
+                Bound::Exact => return entry.score,
+                Bound::Lower => alpha = alpha.max(entry.score),
+                Bound::Upper => beta = beta.min(entry.score),
+            }
+            if alpha >= beta {
+                return entry.score;
+            }
+        }
+        hash_move = entry.best_move;
+    }
+
+    let mut moves = state.legal_moves();
+    // Stuck: scored as `GameState::result` scores it at the root.
+    if moves.is_empty() {
+        return GameResult::Draw.score();
+    }
+    // Search the move that was best at another depth first; it most likely still is.
+    if let Some(index) = moves
+        .iter()
+        .position(|&game_move| config.hash_move_ordering && Some(game_move) == hash_move)
+    {
+        moves[..=index].rotate_right(1);
+    }
+
+    let (original_alpha, original_beta) = (alpha, beta);
+    let mut best: Option<(GameMove, Score)> = None;
+    for game_move in moves {
+        state.make_move(game_move);
+        let child_score = alpha_beta_counted(
+            state,
+            depth - 1,
+            alpha.for_child(),
+            beta.for_child(),
+            tt.as_deref_mut(),
+            config,
+            evaluator,
+            deadline,
+            stats,
+        )
+        .backed_up();
+        state.unmake_move(game_move);
+        if stats.timed_out {
+            // Don't let a cut-off subtree's score reach the table.
+            return child_score;
+        }
+        let improves = match best {
+            None => true,
+            Some((_, best_score)) if state.turn == Player::White => child_score > best_score,
+            Some((_, best_score)) => child_score < best_score,
+        };
+        if improves {
+            best = Some((game_move, child_score));
+        }
+        if state.turn == Player::White {
+            alpha = alpha.max(child_score);
+        } else {
+            beta = beta.min(child_score);
+        }
+        if alpha >= beta {
+            break;
+        }
+    }
+
+    let (best_move, best_score) = best.unwrap();
+    if let Some(tt) = tt {
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= original_beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        tt.store(TtEntry {
+            key,
+            depth,
+            score: best_score,
+            bound,
+            best_move: Some(best_move),
+        });
+    }
+    best_score
+}
+
+/// How many nodes a search visits between looks at the clock.
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+/// Whether a search that has visited `stats.nodes` nodes must stop for `deadline`.
+/// Once it must, `stats.timed_out` stays set.
+fn out_of_time(deadline: Option<Instant>, stats: &mut SearchStats) -> bool {
+    if !stats.timed_out
+        && stats.nodes.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+        && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    {
+        stats.timed_out = true;
+    }
+    stats.timed_out
+}
+
+/// The score of `state` if the search stops there: the game is decided, the
+/// position is a repetition, or there is no depth left. Only the last case asks
+/// `evaluator`.
+fn leaf_score(state: &GameState, depth: i32, evaluator: &dyn Evaluator) -> Option<Score> {
+    if let Some(score) = terminal_score(state) {
+        return Some(score);
+    }
+    if depth <= 0 {
+        return Some(evaluator.evaluate(state));
+    }
+    None
+}
+
+/// The score of `state` if the game is decided or the position is a repetition.
+fn terminal_score(state: &GameState) -> Option<Score> {
+    if let Some(winner) = state.winner() {
+        return Some(Score::for_player(winner));
+    }
+    if state.is_repetition_draw() {
+        return Some(Score::Balanced(0));
+    }
+    None
+}
+
+/// Scores many positions at once, for evaluators such as neural networks that are
+/// much faster on a batch than on one position at a time.
+pub trait BatchEvaluator {
+    /// The value of each of `states` from White's perspective, in order.
+    fn evaluate_batch(&self, states: &[GameState]) -> Vec<Score>;
+}
+
+/// Any evaluator can score a batch one position at a time.
+impl<E: Evaluator> BatchEvaluator for E {
+    fn evaluate_batch(&self, states: &[GameState]) -> Vec<Score> {
+        states.iter().map(|state| self.evaluate(state)).collect()
+    }
+}
+
+/// One entry of the game tree walked by `minimax_batched`, in pre-order.
+enum TreeSlot {
+    /// A leaf scored without the evaluator.
+    Known(Score),
+    /// A leaf scored by the evaluator, by its index among all such leaves.
+    Evaluated(usize),
+    /// An inner node, followed by the subtrees of its `children`.
+    Inner { white: bool, children: usize },
+}
+
+/// Walks the tree for `minimax_batched`, queueing leaves until a batch is full.
+struct BatchedWalk<'a> {
+    evaluator: &'a dyn BatchEvaluator,
+    batch_size: usize,
+    tree: Vec<TreeSlot>,
+    pending: Vec<GameState>,
+    leaf_scores: Vec<Score>,
+}
+
+impl BatchedWalk<'_> {
+    fn record(&mut self, state: &mut GameState, depth: i32) {
+        if let Some(score) = terminal_score(state) {
+            self.tree.push(TreeSlot::Known(score));
+            return;
+        }
+        let moves = if depth > 0 {
+            state.legal_moves()
+        } else {
+            Vec::new()
+        };
+        if moves.is_empty() {
+            let index = self.leaf_scores.len() + self.pending.len();
+            self.tree.push(TreeSlot::Evaluated(index));
+            self.pending.push(state.clone());
+            if self.pending.len() == self.batch_size {
+                self.flush();
+            }
+            return;
+        }
+        self.tree.push(TreeSlot::Inner {
+            white: state.turn == Player::White,
+            children: moves.len(),
+        });
+        for game_move in moves {
+            state.make_move(game_move);
+            self.record(state, depth - 1);
+            state.unmake_move(game_move);
+        }
+    }
+
+    fn flush(&mut self) {
+        let scores = self.evaluator.evaluate_batch(&self.pending);
+        debug_assert_eq!(scores.len(), self.pending.len());
+        self.leaf_scores.extend(scores);
+        self.pending.clear();
+    }
+
+    /// The minimax value of the subtree starting at the next slot of `slots`.
+    fn fold(&self, slots: &mut slice::Iter<TreeSlot>) -> Score {
+        match *slots.next().unwrap() {
+            TreeSlot::Known(score) => score,
+            TreeSlot::Evaluated(index) => self.leaf_scores[index],
+            TreeSlot::Inner { white, children } => {
+                let scores = (0..children).map(|_| self.fold(slots).backed_up());
+                if white {
+                    scores.max().unwrap()
+                } else {
+                    scores.min().unwrap()
+                }
+            }
+        }
+    }
+}
+
+/// The minimax value of `state` searched `depth` plies deep, the same value as
+/// `alpha_beta` gives with a full window and the same evaluator. Nothing is
+/// pruned: the whole tree is walked first and its leaves are handed to `evaluator`
+/// `batch_size` at a time, which only pays off when batches are much cheaper to
+/// score than their positions one by one.
+pub fn minimax_batched(
+    state: &GameState,
+    depth: i32,
+    evaluator: &dyn BatchEvaluator,
+    batch_size: usize,
+) -> Score {
+    let mut walk = BatchedWalk {
+        evaluator,
+        batch_size: batch_size.max(1),
+        tree: Vec::new(),
+        pending: Vec::new(),
+        leaf_scores: Vec::new(),
+    };
+    walk.record(&mut state.clone(), depth);
+    walk.flush();
+    walk.fold(&mut walk.tree.iter())
+}
+
+/// One node on the explicit stack of `alpha_beta_iterative`.
+struct SearchFrame {
+    moves: Vec<GameMove>,
+    /// Index of the next move to search.
+    next: usize,
+    depth: i32,
+    alpha: Score,
+    beta: Score,
+    best: Option<Score>,
+}
+
+impl SearchFrame {
+    fn new(moves: Vec<GameMove>, depth: i32, alpha: Score, beta: Score) -> SearchFrame {
+        SearchFrame {
+            moves,
+            next: 0,
+            depth,
+            alpha,
+            beta,
+            best: None,
+        }
+    }
+}
+
+/// The same search as `alpha_beta` without a transposition table, but driven by an
+/// explicit stack instead of recursion, so deep searches can't overflow the call stack.
+pub fn alpha_beta_iterative(state: &mut GameState, depth: i32, alpha: Score, beta: Score) -> Score {
+    alpha_beta_iterative_with(state, depth, alpha, beta, &EvalWeights::default())
+}
+
+/// `alpha_beta_iterative` evaluating leaves with `weights`.
+pub fn alpha_beta_iterative_with(
+    state: &mut GameState,
+    depth: i32,
+    alpha: Score,
+    beta: Score,
+    weights: &EvalWeights,
+) -> Score {
+    alpha_beta_iterative_counted(
+        state,
+        depth,
+        alpha,
+        beta,
+        weights,
+        None,
+        &mut SearchStats::default(),
+    )
+}
+
+/// `alpha_beta_iterative_with` scoring leaves with `evaluator`, and adding the
+/// nodes it visits to `stats`. Gives up once `deadline` passes, like
+/// `alpha_beta_counted`.
+fn alpha_beta_iterative_counted(
+    state: &mut GameState,
+    depth: i32,
+    alpha: Score,
+    beta: Score,
+    evaluator: &dyn Evaluator,
+    deadline: Option<Instant>,
+    stats: &mut SearchStats,
+) -> Score {
+    stats.nodes += 1;
+    if let Some(score) = leaf_score(state, depth, evaluator) {
+        return score;
+    }
+    let moves = state.legal_moves();
+    if moves.is_empty() {
+        return GameResult::Draw.score();
+    }
+
+    let mut stack = vec![SearchFrame::new(moves, depth, alpha, beta)];
+    // The score of the child just searched, waiting to be folded into its parent.
+    let mut returned: Option<Score> = None;
+    loop {
+        let frame = stack.last_mut().unwrap();
+        if let Some(child_score) = returned.take() {
+            let child_score = child_score.backed_up();
+            state.unmake_move(frame.moves[frame.next - 1]);
+            let improves = match frame.best {
+                None => true,
+                Some(best) if state.turn == Player::White => child_score > best,
+                Some(best) => child_score < best,
+            };
+            if improves {
+                frame.best = Some(child_score);
+            }
+            if state.turn == Player::White {
+                frame.alpha = frame.alpha.max(child_score);
+            } else {
+                frame.beta = frame.beta.min(child_score);
+            }
+            if frame.alpha >= frame.beta {
+                frame.next = frame.moves.len();
+            }
+        }
+
+        if frame.next == frame.moves.len() {
+            let score = frame.best.unwrap();
+            stack.pop();
+            if stack.is_empty() {
+                return score;
+            }
+            returned = Some(score);
+            continue;
+        }
+
+        if out_of_time(deadline, stats) {
+            // Every frame below the top one has its current move on the board.
+            stack.pop();
+            while let Some(frame) = stack.pop() {
+                state.unmake_move(frame.moves[frame.next - 1]);
+            }
+            return Score::Balanced(0);
+        }
+        let game_move = frame.moves[frame.next];
+        frame.next += 1;
+        let (child_depth, alpha, beta) = (
+            frame.depth - 1,
+            frame.alpha.for_child(),
+            frame.beta.for_child(),
+        );
+        state.make_move(game_move);
+        stats.nodes += 1;
+        if let Some(score) = leaf_score(state, child_depth, evaluator) {
+            returned = Some(score);
+            continue;
+        }
+        let moves = state.legal_moves();
+        if moves.is_empty() {
+            returned = Some(GameResult::Draw.score());
+        } else {
+            stack.push(SearchFrame::new(moves, child_depth, alpha, beta));
+        }
+    }
+}
+
+/// How `MinimaxEngine` chooses among root moves with the same score. Every rule
+/// is deterministic, so the same position and settings always give the same move.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// The first of the moves in generation order. The cheapest rule, because the
+    /// search doesn't have to confirm that later moves really are as good.
+    #[default]
+    FirstGenerated,
+    /// The move whose destination is closest to the center of the board.
+    MostCentral,
+    /// The first move that covers an opponent's piece, if any does.
+    MostAggressive,
+    /// A pseudo-random move, drawn from the seed and the position.
+    Random(u64),
+    /// The move that leaves the opponent the most replies to choose from, and so
+    /// the most chances to go wrong. Meant for lost positions, where every move
+    /// scores the same.
+    Swindle,
+}
+
+impl TieBreak {
+    /// Pick one of `tied`, which must not be empty, to play in `state`.
+    fn choose(self, state: &GameState, tied: &[GameMove]) -> GameMove {
+        match self {
+            TieBreak::FirstGenerated => tied[0],
+            TieBreak::MostCentral => *tied
+                .iter()
+                .min_by_key(|game_move| game_move.dest().map_or(usize::MAX, distance_from_center))
+                .unwrap(),
+            TieBreak::MostAggressive => *tied
+                .iter()
+                .find(|game_move| {
+                    game_move.dest().is_some_and(|dest| {
+                        state.board.stack(dest).top_color() == state.turn.other().color()
+                    })
+                })
+                .unwrap_or(&tied[0]),
+            TieBreak::Random(seed) => {
+                let mut rng = StdRng::seed_from_u64(seed ^ state.zobrist());
+                tied[rng.gen_range(0..tied.len())]
+            }
+            TieBreak::Swindle => {
+                let mut scratch = state.clone();
+                *tied
+                    .iter()
+                    .min_by_key(|&&game_move| {
+                        scratch.make_move(game_move);
+                        let replies = scratch.legal_move_count();
+                        scratch.unmake_move(game_move);
+                        Reverse(replies)
+                    })
+                    .unwrap()
+            }
+        }
+    }
+}
+
+/// Tunables for `MinimaxEngine`, built up from the defaults with the `with_*`
+/// methods and passed once to `MinimaxEngine::from_config`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EngineConfig {
+    /// The deepest iteration of iterative deepening.
+    pub depth: i32,
+    pub time_limit: Option<Duration>,
+    /// Transposition table slots and replacement policy; `None` searches without one.
+    pub transposition_table: Option<(usize, ReplacementPolicy)>,
+    /// Search with `alpha_beta_iterative`, which ignores the transposition table.
+    pub iterative: bool,
+    /// Search the transposition table's best move first.
+    pub hash_move_ordering: bool,
+    pub weights: EvalWeights,
+    pub tie_break: TieBreak,
+    /// Root moves scoring within this many points of the best move count as tied
+    /// with it, so a positive margin lets the engine play slightly worse moves.
+    /// Forced wins and losses are only tied with each other.
+    pub tie_margin: i32,
+    /// Only search one root move from each set of moves that are mirror images
+    /// under a symmetry of the position; see `GameState::distinct_moves`.
+    pub root_symmetry: bool,
+    /// A ceiling on the memory the search may use, in bytes. The transposition
+    /// table is shrunk to at most half of it, or dropped, and iterative deepening
+    /// stops before a depth whose estimated footprint would exceed it; see
+    /// `MinimaxEngine::estimated_memory_bytes`. The first iteration always runs.
+    pub max_memory_bytes: Option<usize>,
+    /// The seed of the Zobrist keys the engine's transposition table uses; see
+    /// `TranspositionTable::with_seed`. Defaults to `zobrist::DEFAULT_SEED`.
+    pub zobrist_seed: u64,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            depth: 3,
+            time_limit: None,
+            transposition_table: None,
+            iterative: false,
+            hash_move_ordering: true,
+            weights: EvalWeights::default(),
+            tie_break: TieBreak::default(),
+            tie_margin: 0,
+            root_symmetry: false,
+            max_memory_bytes: None,
+            zobrist_seed: zobrist::DEFAULT_SEED,
+        }
+    }
+}
+
+impl EngineConfig {
+    pub fn with_depth(self, depth: i32) -> EngineConfig {
+        EngineConfig { depth, ..self }
+    }
+
+    pub fn with_time_limit(self, time_limit: Duration) -> EngineConfig {
+        EngineConfig {
+            time_limit: Some(time_limit),
+            ..self
+        }
+    }
+
+    pub fn with_transposition_table(self, size: usize, policy: ReplacementPolicy) -> EngineConfig {
+        EngineConfig {
+            transposition_table: Some((size, policy)),
+            ..self
+        }
+    }
+
+    pub fn with_iterative_search(self) -> EngineConfig {
+        EngineConfig {
+            iterative: true,
+            ..self
+        }
+    }
+
+    pub fn with_hash_move_ordering(self, hash_move_ordering: bool) -> EngineConfig {
+        EngineConfig {
+            hash_move_ordering,
+            ..self
+        }
+    }
+
+    pub fn with_weights(self, weights: EvalWeights) -> EngineConfig {
+        EngineConfig { weights, ..self }
+    }
+
+    pub fn with_tie_break(self, tie_break: TieBreak) -> EngineConfig {
+        EngineConfig { tie_break, ..self }
+    }
+
+    pub fn with_tie_margin(self, tie_margin: i32) -> EngineConfig {
+        EngineConfig { tie_margin, ..self }
+    }
+
+    pub fn with_root_symmetry(self, root_symmetry: bool) -> EngineConfig {
+        EngineConfig {
+            root_symmetry,
+            ..self
+        }
+    }
+
+    pub fn with_max_memory_bytes(self, max_memory_bytes: usize) -> EngineConfig {
+        EngineConfig {
+            max_memory_bytes: Some(max_memory_bytes),
+            ..self
+        }
+    }
+
+    pub fn with_zobrist_seed(self, zobrist_seed: u64) -> EngineConfig {
+        EngineConfig {
+            zobrist_seed,
+            ..self
+        }
+    }
+
+    /// Check the settings a search can't run with: a depth below one, which
+    /// would leave no move to choose, or a transposition table with no slots.
+    pub fn validate(&self) -> Result<(), EngineError> {
+        if self.depth < 1 {
+            return Err(EngineError::InvalidDepth(self.depth));
+        }
+        if matches!(self.transposition_table, Some((0, _))) {
+            return Err(EngineError::EmptyTranspositionTable);
+        }
+        Ok(())
+    }
+}
+
+/// Why a search could not run or finish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineError {
+    /// The search depth must be at least one ply.
+    InvalidDepth(i32),
+    /// A transposition table was configured with no slots.
+    EmptyTranspositionTable,
+    /// A worker thread of a parallel search panicked.
+    WorkerPanicked,
+}
+
+impl Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            EngineError::InvalidDepth(depth) => {
+                write!(f, "search depth must be at least 1, not {depth}")
+            }
+            EngineError::EmptyTranspositionTable => {
+                write!(f, "the transposition table needs at least one slot")
+            }
+            EngineError::WorkerPanicked => write!(f, "a search thread panicked"),
+        }
+    }
+}
+
+impl Error for EngineError {}
+
+/// Named playing strengths, weakest first, for `MinimaxEngine::with_difficulty`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Difficulty {
+    Beginner,
+    Easy,
+    Medium,
+    Hard,
+    Expert,
+}
+
+impl Difficulty {
+    pub fn all() -> [Difficulty; 5] {
+        [
+            Difficulty::Beginner,
+            Difficulty::Easy,
+            Difficulty::Medium,
+            Difficulty::Hard,
+            Difficulty::Expert,
+        ]
+    }
+
+    /// The engine settings for this level. The weaker levels look only a move or
+    /// two ahead and pick at random among moves that are nearly as good as the
+    /// best, so they vary their play and often miss the strongest move.
+    pub fn config(self) -> EngineConfig {
+        let config = EngineConfig::default();
+        match self {
+            Difficulty::Beginner => config
+                .with_depth(1)
+                .with_tie_break(TieBreak::Random(0))
+                .with_tie_margin(4),
+            Difficulty::Easy => config
+                .with_depth(2)
+                .with_tie_break(TieBreak::Random(0))
+                .with_tie_margin(2),
+            Difficulty::Medium => config
+                .with_depth(3)
+                .with_tie_break(TieBreak::Random(0))
+                .with_tie_margin(1),
+            Difficulty::Hard => config
+                .with_depth(4)
+                .with_time_limit(Duration::from_secs(1))
+                .with_transposition_table(1 << 16, ReplacementPolicy::default())
+                .with_tie_break(TieBreak::MostCentral),
+            Difficulty::Expert => config
+                .with_depth(6)
+                .with_time_limit(Duration::from_secs(5))
+                .with_transposition_table(1 << 20, ReplacementPolicy::default()),
+        }
+    }
+}
+
+/// Work done by a search.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// Positions visited, including the root and the leaves.
+    pub nodes: u64,
+    /// Positions whose transposition table entry was deep enough to use.
+    pub tt_hits: u64,
+    /// The time limit ran out partway through, and the unfinished iteration was
+    /// thrown away.
+    pub timed_out: bool,
+}
+
+/// Everything a search found about a position.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchResult {
+    /// `None` if there are no legal moves.
+    pub best_move: Option<GameMove>,
+    /// The value of the position, from White's perspective.
+    pub score: Score,
+    /// The principal variation: `best_move` followed by the replies the engine
+    /// expects, up to `depth_reached` moves. It ends early if the game does.
+    pub pv: Vec<GameMove>,
+    pub depth_reached: u32,
+    pub stats: SearchStats,
+}
+
+impl SearchResult {
+    /// The result for a game that is already over: no move and nothing searched.
+    fn finished(result: GameResult) -> SearchResult {
+        SearchResult {
+            best_move: None,
+            score: result.score(),
+            pv: Vec::new(),
+            depth_reached: 0,
+            stats: SearchStats::default(),
+        }
+    }
+}
+
+/// Alpha-beta minimax, deepened one ply at a time until `depth` is reached or
+/// `time_limit` has elapsed. An iteration still running at the time limit is
+/// abandoned, and the move of the last completed one is played.
+pub struct MinimaxEngine {
+    config: EngineConfig,
+    transposition_table: Option<TranspositionTable>,
+    /// Replaces `config.weights` for scoring leaves when set.
+    evaluator: Option<Box<dyn Evaluator + Send>>,
+    /// When the iteration being searched has to be abandoned.
+    deadline: Option<Instant>,
+}
+
+impl MinimaxEngine {
+    pub fn new(depth: i32) -> MinimaxEngine {
+        MinimaxEngine::from_config(EngineConfig::default().with_depth(depth))
+    }
+
+    pub fn from_config(config: EngineConfig) -> MinimaxEngine {
+        MinimaxEngine {
+            config,
+            transposition_table: config.transposition_table.and_then(|(size, policy)| {
+                let size = match config.max_memory_bytes {
+                    Some(budget) => size.min(budget / 2 / policy.slot_bytes()),
+                    None => size,
+                };
+                (size > 0)
+                    .then(|| TranspositionTable::new(size, policy).with_seed(config.zobrist_seed))
+            }),
+            evaluator: None,
+            deadline: None,
+        }
+    }
+
+    /// Rebuild the engine for `config`, keeping the evaluator.
+    fn reconfigured(self, config: EngineConfig) -> MinimaxEngine {
+        MinimaxEngine {
+            evaluator: self.evaluator,
+            ..MinimaxEngine::from_config(config)
+        }
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// A rough upper bound on the memory a search to `depth` plies takes: the
+    /// transposition table, the copies of the root position, and for each ply a
+    /// history entry and a full list of moves.
+    pub fn estimated_memory_bytes(&self, depth: i32) -> usize {
+        let cells = BOARD_ROWS * BOARD_COLS;
+        let max_moves = cells * NUM_SIZES + cells * (cells - 1) + 1;
+        let per_ply = mem::size_of::<HistoryEntry>() + max_moves * mem::size_of::<GameMove>();
+        let table = self
+            .transposition_table
+            .as_ref()
+            .map_or(0, TranspositionTable::memory_bytes);
+        table + 2 * mem::size_of::<GameState>() + depth.max(0) as usize * per_ply
+    }
+
+    /// Score leaves with `evaluator` instead of the configured weights.
+    pub fn with_evaluator(self, evaluator: impl Evaluator + Send + 'static) -> MinimaxEngine {
+        MinimaxEngine {
+            evaluator: Some(Box::new(evaluator)),
+            ..self
+        }
+    }
+
+    /// An engine playing at `difficulty`. Levels that break ties at random draw
+    /// their seed from `rng`, so engines at the same level vary their play unless
+    /// they are given identically seeded generators.
+    pub fn with_difficulty(difficulty: Difficulty, rng: &mut impl Rng) -> MinimaxEngine {
+        let mut config = difficulty.config();
+        if let TieBreak::Random(_) = config.tie_break {
+            config.tie_break = TieBreak::Random(rng.gen());
+        }
+        MinimaxEngine::from_config(config)
+    }
+
+    /// Search with `alpha_beta_iterative` instead of the recursive search. The
+    /// transposition table is not used in this mode.
+    pub fn with_iterative_search(self) -> MinimaxEngine {
+        let config = self.config.with_iterative_search();
+        self.reconfigured(config)
+    }
+
+    pub fn with_time_limit(self, time_limit: Duration) -> MinimaxEngine {
+        let config = self.config.with_time_limit(time_limit);
+        self.reconfigured(config)
+    }
+
+    pub fn with_transposition_table(self, size: usize, policy: ReplacementPolicy) -> MinimaxEngine {
+        let config = self.config.with_transposition_table(size, policy);
+        self.reconfigured(config)
+    }
+
+    /// The value of playing `game_move` in `state`, searched `depth` plies deep
+    /// counting the move itself.
+    pub fn score_move(&mut self, state: &GameState, game_move: GameMove, depth: i32) -> Score {
+        let mut state = state.clone();
+        state.make_move(game_move);
+        let (alpha, beta) = (Score::MIN, Score::MAX);
+        self.search_child(
+            &mut state,
+            depth - 1,
+            alpha,
+            beta,
+            &mut SearchStats::default(),
+        )
+        .backed_up()
+    }
+
+    /// Search `state` the way `choose_move` does, deepening one ply at a time until
+    /// the configured depth or time limit. An iteration still running when the time
+    /// limit passes is abandoned, except the first, so there is always a move. The
+    /// result describes the deepest completed iteration; its `stats` count the
+    /// nodes of every iteration.
+    ///
+    /// Fails without searching if the configuration is invalid; see
+    /// `EngineConfig::validate`.
+    pub fn search(&mut self, state: &GameState) -> Result<SearchResult, EngineError> {
+        self.config.validate()?;
+        if let Some(result) = state.result() {
+            return Ok(SearchResult::finished(result));
+        }
+        let start = Instant::now();
+        let mut result = self.search_root(state, 1)?;
+        for depth in 2..=self.config.depth {
+            if self
+                .config
+                .time_limit
+                .is_some_and(|limit| start.elapsed() >= limit)
+            {
+                break;
+            }
+            if self
+                .config
+                .max_memory_bytes
+                .is_some_and(|budget| self.estimated_memory_bytes(depth) > budget)
+            {
+                break;
+            }
+            self.deadline = self.config.time_limit.map(|limit| start + limit);
+            let iteration = self.search_root(state, depth);
+            self.deadline = None;
+            let mut iteration = iteration?;
+            iteration.stats.nodes += result.stats.nodes;
+            iteration.stats.tt_hits += result.stats.tt_hits;
+            if iteration.stats.timed_out {
+                result.stats = iteration.stats;
+                break;
+            }
+            result = iteration;
+        }
+        Ok(result)
+    }
+
+    /// The best move at exactly `depth` plies, choosing among equally good moves
+    /// (or moves within the configured tie margin) with the configured `TieBreak`,
+    /// and the line expected to follow it. Fails if `depth` is less than one or the
+    /// configuration is invalid.
+    pub fn search_root(
+        &mut self,
+        state: &GameState,
+        depth: i32,
+    ) -> Result<SearchResult, EngineError> {
+        self.config.with_depth(depth).validate()?;
+        if let Some(result) = state.result() {
+            return Ok(SearchResult::finished(result));
+        }
+        let mut stats = SearchStats::default();
+        let best = self.best_at_depth(state, depth, &mut stats);
+        if stats.timed_out {
+            return Ok(SearchResult {
+                best_move: best.map(|(game_move, _)| game_move),
+                score: Score::Balanced(0),
+                pv: Vec::new(),
+                depth_reached: 0,
+                stats,
+            });
+        }
+        // Follow the line by asking for the best reply at each remaining depth, so
+        // that it matches what the engine would actually play. This isn't counted
+        // in the statistics, nor cut off by the time limit.
+        self.deadline = None;
+        let mut pv = Vec::new();
+        let mut line = state.clone();
+        let mut next = best;
+        let mut remaining = depth;
+        while let Some((game_move, _)) = next {
+            pv.push(game_move);
+            remaining -= 1;
+            if remaining <= 0 {
+                break;
+            }
+            line.make_move(game_move);
+            next = self.best_at_depth(&line, remaining, &mut SearchStats::default());
+        }
+        Ok(SearchResult {
+            best_move: best.map(|(game_move, _)| game_move),
+            score: best.map_or_else(|| self.evaluator().evaluate(state), |(_, score)| score),
+            pv,
+            depth_reached: depth as u32,
+            stats,
+        })
+    }
+
+    fn evaluator(&self) -> &dyn Evaluator {
+        match &self.evaluator {
+            Some(evaluator) => evaluator.as_ref(),
+            None => &self.config.weights,
+        }
+    }
+
+    /// Search `state` below the root with the configured search and evaluator.
+    fn search_child(
+        &mut self,
+        state: &mut GameState,
+        depth: i32,
+        alpha: Score,
+        beta: Score,
+        stats: &mut SearchStats,
+    ) -> Score {
+        let evaluator: &dyn Evaluator = match &self.evaluator {
+            Some(evaluator) => evaluator.as_ref(),
+            None => &self.config.weights,
+        };
+        if self.config.iterative {
+            alpha_beta_iterative_counted(state, depth, alpha, beta, evaluator, self.deadline, stats)
+        } else {
+            alpha_beta_counted(
+                state,
+                depth,
+                alpha,
+                beta,
+                self.transposition_table.as_mut(),
+                &self.config,
+                evaluator,
+                self.deadline,
+                stats,
+            )
+        }
+    }
+
+    fn best_at_depth(
+        &mut self,
+        state: &GameState,
+        depth: i32,
+        stats: &mut SearchStats,
+    ) -> Option<(GameMove, Score)> {
+        let (tie_break, margin) = (self.config.tie_break, self.config.tie_margin);
+        let collect_ties = tie_break != TieBreak::FirstGenerated || margin > 0;
+        let white = state.turn == Player::White;
+        // The worst score still tied with `best` for the side to move.
+        let tie_limit = |best: Score| {
+            if white {
+                best.shifted(-margin)
+            } else {
+                best.shifted(margin)
+            }
+        };
+        let (mut alpha, mut beta) = (Score::MIN, Score::MAX);
+        let mut best: Option<Score> = None;
+        let mut scored = Vec::new();
+        let mut state = state.clone();
+        stats.nodes += 1;
+        let mut root_moves = state.legal_moves_wins_first();
+        if self.config.root_symmetry {
+            let distinct = state.distinct_moves();
+            root_moves.retain(|game_move| distinct.contains(game_move));
+        }
+        for game_move in root_moves {
+            // Widen the window past the tie limit so that every move tied with the
+            // best so far gets an exact score instead of a bound.
+            let (search_alpha, search_beta) = match (collect_ties, white) {
+                (false, _) => (alpha, beta),
+                (true, true) => (tie_limit(alpha).just_below(), beta),
+                (true, false) => (alpha, tie_limit(beta).just_above()),
+            };
+            state.make_move(game_move);
+            let score = self
+                .search_child(
+                    &mut state,
+                    depth - 1,
+                    search_alpha.for_child(),
+                    search_beta.for_child(),
+                    stats,
+                )
+                .backed_up();
+            state.unmake_move(game_move);
+            if stats.timed_out {
+                break;
+            }
+            let improves = match best {
+                None => true,
+                Some(best_score) if white => score > best_score,
+                Some(best_score) => score < best_score,
+            };
+            if improves {
+                best = Some(score);
+                if white {
+                    alpha = score;
+                } else {
+                    beta = score;
+                }
+            }
+            scored.push((game_move, score));
+        }
+
+        // A move searched before the best one was found can't score as well as it,
+        // so the first tied move is the first one that reached the best score.
+        let limit = tie_limit(best?);
+        let tied: Vec<GameMove> = scored
+            .iter()
+            .filter(|&&(_, score)| {
+                if white {
+                    score >= limit
+                } else {
+                    score <= limit
+                }
+            })
+            .map(|&(game_move, _)| game_move)
+            .collect();
+        let choice = tie_break.choose(&state, &tied);
+        scored
+            .into_iter()
+            .find(|&(game_move, _)| game_move == choice)
+    }
+}
+
+/// Returns `None` when the search fails as well as when there is no move.
+impl Engine for MinimaxEngine {
+    fn choose_move(&mut self, state: &GameState) -> Option<GameMove> {
+        self.search(state).ok()?.best_move
+    }
+
+    fn name(&self) -> &str {
+        "minimax"
+    }
+}
+
+/// Plays uniformly random legal moves.
+pub struct RandomEngine<R: Rng> {
+    rng: R,
+}
+
+impl<R: Rng> RandomEngine<R> {
+    pub fn new(rng: R) -> RandomEngine<R> {
+        RandomEngine { rng }
+    }
+}
+
+impl<R: Rng> Engine for RandomEngine<R> {
+    fn choose_move(&mut self, state: &GameState) -> Option<GameMove> {
+        state
+            .random_move(&mut self.rng)
+            .map(|(game_move, _)| game_move)
+    }
+
+    fn name(&self) -> &str {
+        "random"
+    }
+}
+
+/// Looks only one move ahead: plays the move whose resulting position has the best
+/// static score for the side to move. Meant as a weak opponent for teaching.
+pub struct GreedyEngine<R: Rng> {
+    rng: R,
+    /// Pick uniformly among equally scored moves instead of taking the first.
+    randomize_ties: bool,
+}
+
+impl<R: Rng> GreedyEngine<R> {
+    pub fn new(rng: R, randomize_ties: bool) -> GreedyEngine<R> {
+        GreedyEngine {
+            rng,
+            randomize_ties,
+        }
+    }
+}
+
+impl<R: Rng> Engine for GreedyEngine<R> {
+    fn choose_move(&mut self, state: &GameState) -> Option<GameMove> {
+        let scored: Vec<(GameMove, Score)> = state
+            .branch()
+            .into_iter()
+            .map(|(game_move, child)| (game_move, child.raw_score()))
+            .collect();
+        let best = if state.turn == Player::White {
+            scored.iter().map(|&(_, score)| score).max()
+        } else {
+            scored.iter().map(|&(_, score)| score).min()
+        }?;
+        let mut ties = scored
+            .into_iter()
+            .filter(|&(_, score)| score == best)
+            .map(|(game_move, _)| game_move);
+        if self.randomize_ties {
+            ties.choose(&mut self.rng)
+        } else {
+            ties.next()
+        }
+    }
+
+    fn name(&self) -> &str {
+        "greedy"
+    }
+}
+
+struct MctsNode {
+    state: GameState,
+    /// The move that led here from the parent, and who made it.
+    game_move: Option<GameMove>,
+    mover: Player,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<GameMove>,
+    visits: u32,
+    /// Accumulated rollout results from the point of view of `mover`.
+    wins: f64,
+}
+
+/// Monte Carlo tree search with UCT selection and uniformly random rollouts.
+pub struct MctsEngine<R: Rng> {
+    iterations: u32,
+    max_rollout_plies: u32,
+    exploration: f64,
+    rng: R,
+}
+
+impl<R: Rng> MctsEngine<R> {
+    pub fn new(iterations: u32, rng: R) -> MctsEngine<R> {
+        MctsEngine {
+            iterations,
+            max_rollout_plies: 64,
+            exploration: std::f64::consts::SQRT_2,
+            rng,
+        }
+    }
+
+    /// Play random moves from `state` with `GameState::rollout` and return the
+    /// winner, if any.
+    fn rollout(&mut self, mut state: GameState) -> Option<Player> {
+        match state.rollout(&mut self.rng, self.max_rollout_plies as usize) {
+            GameResult::Win(winner) => Some(winner),
+            GameResult::Draw => None,
+        }
+    }
+}
+
+impl<R: Rng> MctsEngine<R> {
+    /// Run the search from `state`, considering only `root_moves` at the root.
+    fn search_among(&mut self, state: &GameState, root_moves: Vec<GameMove>) -> Option<GameMove> {
+        let mut tree = vec![MctsNode {
+            state: state.clone(),
+            game_move: None,
+            mover: state.turn.other(),
+            parent: None,
+            children: Vec::new(),
+            untried: root_moves,
+            visits: 0,
+            wins: 0.0,
+        }];
+
+        for _ in 0..self.iterations {
+            // Selection.
+            let mut index = 0;
+            while tree[index].untried.is_empty() && !tree[index].children.is_empty() {
+                let parent_visits = f64::from(tree[index].visits);
+                let exploration = self.exploration;
+                index = *tree[index]
+                    .children
+                    .iter()
+                    .max_by(|&&a, &&b| {
+                        let uct = |child: &MctsNode| {
+                            child.wins / f64::from(child.visits)
+                                + exploration
+                                    * (parent_visits.ln() / f64::from(child.visits)).sqrt()
+                        };
+                        uct(&tree[a]).total_cmp(&uct(&tree[b]))
+                    })
+                    .unwrap();
+            }
+
+            // Expansion.
+            if !tree[index].untried.is_empty() {
+                let pick = self.rng.gen_range(0..tree[index].untried.len());
+                let game_move = tree[index].untried.swap_remove(pick);
+                let mut child_state = tree[index].state.clone();
+                child_state.apply_move(game_move);
+                let untried = match child_state.raw_score() {
+                    Score::Balanced(_) => child_state.legal_moves(),
+                    _ => Vec::new(),
+                };
+                let child = tree.len();
+                tree.push(MctsNode {
+                    mover: tree[index].state.turn,
+                    state: child_state,
+                    game_move: Some(game_move),
+                    parent: Some(index),
+                    children: Vec::new(),
+                    untried,
+                    visits: 0,
+                    wins: 0.0,
+                });
+                tree[index].children.push(child);
+                index = child;
+            }
+
+            // Simulation.
+            let winner = self.rollout(tree[index].state.clone());
+
+            // Backpropagation.
+            let mut current = Some(index);
+            while let Some(node_index) = current {
+                let node = &mut tree[node_index];
+                node.visits += 1;
+                node.wins += match winner {
+                    Some(winner) if winner == node.mover => 1.0,
+                    Some(_) => 0.0,
+                    None => 0.5,
+                };
+                current = node.parent;
+            }
+        }
+
+        tree[0]
+            .children
+            .iter()
+            .max_by_key(|&&child| tree[child].visits)
+            .and_then(|&child| tree[child].game_move)
+    }
+}
+
+impl<R: Rng> Engine for MctsEngine<R> {
+    fn choose_move(&mut self, state: &GameState) -> Option<GameMove> {
+        self.search_among(state, state.legal_moves())
+    }
+
+    fn name(&self) -> &str {
+        "mcts"
+    }
+}
+
+/// A shallow minimax pass over the root moves followed by MCTS on the survivors.
+/// The minimax pass plays a forced win outright and drops moves that lose by
+/// force; MCTS then spends its whole budget on the moves that are left.
+pub struct HybridEngine<R: Rng> {
+    /// Plies searched by the minimax pass, counting the root move; 0 skips it.
+    minimax_depth: i32,
+    mcts: MctsEngine<R>,
+}
+
+impl<R: Rng> HybridEngine<R> {
+    pub fn new(minimax_depth: i32, mcts_iterations: u32, rng: R) -> HybridEngine<R> {
+        HybridEngine {
+            minimax_depth,
+            mcts: MctsEngine::new(mcts_iterations, rng),
+        }
+    }
+}
+
+impl<R: Rng> Engine for HybridEngine<R> {
+    fn choose_move(&mut self, state: &GameState) -> Option<GameMove> {
+        let moves = state.legal_moves();
+        if self.minimax_depth <= 0 || moves.len() <= 1 {
+            return self.mcts.search_among(state, moves);
+        }
+        let mover = state.turn;
+        let mut scratch = state.clone();
+        let mut survivors = Vec::new();
+        for &game_move in &moves {
+            scratch.make_move(game_move);
+            let score = alpha_beta(
+                &mut scratch,
+                self.minimax_depth - 1,
+                Score::MIN,
+                Score::MAX,
+                None,
+            );
+            scratch.unmake_move(game_move);
+            if score.is_win_for(mover) {
+                return Some(game_move);
+            }
+            if !score.is_win_for(mover.other()) {
+                survivors.push(game_move);
+            }
+        }
+        // Every move loses against best play; leave it to MCTS to find the
+        // one most likely to trip the opponent up.
+        if survivors.is_empty() {
+            survivors = moves;
+        }
+        if survivors.len() == 1 {
+            return survivors.pop();
+        }
+        self.mcts.search_among(state, survivors)
+    }
+
+    fn name(&self) -> &str {
+        "hybrid"
+    }
+}
+
+/// Count the leaf nodes of the game tree `depth` plies below `state`.
+/// Used to validate move generation against known node counts.
+pub fn perft(state: &GameState, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    if depth == 1 {
+        return state.legal_move_count() as u64;
+    }
+    let children = state.branch();
+    children
+        .iter()
+        .map(|(_, child)| perft(child, depth - 1))
+        .sum()
+}
+
+/// Same as `perft`, but the root moves are split across `threads` worker threads
+/// and the subtree counts are summed at the end.
+pub fn perft_parallel(state: &GameState, depth: u32, threads: usize) -> u64 {
+    if depth <= 1 || threads <= 1 {
+        return perft(state, depth);
+    }
+    let children = state.branch();
+    let chunk_size = children.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = children
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|(_, child)| perft(child, depth - 1))
+                        .sum::<u64>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .sum()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn perft_parallel_matches_perft() {
+        let opening = GameState::new();
+        let (_, after_one) = opening.branch().swap_remove(5);
+        let (_, midgame) = after_one.branch().swap_remove(20);
+        for state in [opening, midgame] {
+            let serial = perft(&state, 3);
+            for threads in [2, 4, 7] {
+                assert_eq!(perft_parallel(&state, 3, threads), serial);
+            }
+        }
+    }
+
+    /// Times serial and parallel perft at depth 4. Run with
+    /// `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn perft_benchmark() {
+        let state = GameState::new();
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+        let start = Instant::now();
+        let serial = perft(&state, 4);
+        let serial_time = start.elapsed();
+        let start = Instant::now();
+        let parallel = perft_parallel(&state, 4, threads);
+        let parallel_time = start.elapsed();
+
+        assert_eq!(serial, parallel);
+        println!(
+            "perft(4) = {serial}: {serial_time:?} serial, {parallel_time:?} on {threads} threads"
+        );
+    }
+
+    #[test]
+    fn the_same_seed_plays_the_same_game() {
+        let game = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut state = GameState::new();
+            let mut played = Vec::new();
+            while let Some((game_move, next_state)) = state.random_move(&mut rng) {
+                played.push(game_move);
+                state = next_state;
+                if played.len() == 30 {
+                    break;
+                }
+            }
+            played
+        };
+        let first = game(7);
+        assert!(!first.is_empty());
+        assert_eq!(game(7), first);
+        assert_ne!(game(8), first);
+    }
+
+    #[test]
+    fn legal_move_count_matches_branch() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..10 {
+            let mut state = GameState::new();
+            for _ in 0..24 {
+                assert_eq!(state.legal_move_count(), state.branch().len());
+                assert_eq!(state.legal_move_count(), state.legal_moves().len());
+                let Some((_, next_state)) = state.random_move(&mut rng) else {
+                    break;
+                };
+                state = next_state;
+            }
+        }
+    }
+
+    #[test]
+    fn a_handicapped_player_never_places_a_missing_size() {
+        let state = GameState::with_reserves([3, 3, 3, 0], [NUM_EACH_SIZE; NUM_SIZES]);
+        assert!(state
+            .legal_moves()
+            .iter()
+            .all(|game_move| !matches!(game_move, GameMove::Place { size: 3, .. })));
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..50 {
+            let mut scratch = state.clone();
+            for _ in 0..40 {
+                let Some((game_move, child)) = scratch.random_move(&mut rng) else {
+                    break;
+                };
+                if scratch.turn == Player::White {
+                    assert!(!matches!(game_move, GameMove::Place { size: 3, .. }));
+                }
+                scratch = child;
+                assert!(scratch.white_pieces.iter().all(|&count| count >= 0));
+                assert!(scratch.black_pieces.iter().all(|&count| count >= 0));
+            }
+        }
+    }
+
+    #[test]
+    fn every_engine_plays_a_legal_move_from_the_start() {
+        let mut engines: Vec<Box<dyn Engine>> = vec![
+            Box::new(MinimaxEngine::new(2)),
+            Box::new(MctsEngine::new(50, StdRng::seed_from_u64(0))),
+            Box::new(RandomEngine::new(StdRng::seed_from_u64(0))),
+        ];
+        let state = GameState::new();
+        let legal = state.legal_moves();
+        for engine in &mut engines {
+            let game_move = engine.choose_move(&state);
+            assert!(
+                game_move.is_some_and(|game_move| legal.contains(&game_move)),
+                "{} chose {game_move:?}",
+                engine.name()
+            );
+        }
+    }
+
+    #[test]
+    fn grids_match_the_position_they_describe() {
+        let grid = GameState::from_grid(
+            [
+                ["W3", ".", ".", "."],
+                [".", "b0|W2", ".", "."],
+                [".", ".", ".", "."],
+                [".", ".", ".", "."],
+            ],
+            Player::Black,
+        )
+        .unwrap();
+        let played = play(&["3@a1", "0@b2", "2@b2"]);
+        assert_eq!(grid.position_string(), played.position_string());
+        assert!(grid.turn == played.turn);
+        assert_eq!(grid.white_pieces, played.white_pieces);
+        assert_eq!(grid.black_pieces, played.black_pieces);
+        assert_eq!(grid.zobrist(), played.zobrist());
+    }
+
+    #[test]
+    fn grids_reject_inconsistent_stocks() {
+        let too_many = GameState::from_grid(
+            [
+                ["W3", "W3", "W3", "W3"],
+                [".", ".", ".", "."],
+                [".", ".", ".", "."],
+                [".", ".", ".", "."],
+            ],
+            Player::White,
+        );
+        assert!(matches!(
+            too_many,
+            Err(PositionError::TooManyPieces {
+                player: Player::White,
+                size: 3
+            })
+        ));
+        let bad_stack = GameState::from_grid(
+            [
+                ["W3|b0", ".", ".", "."],
+                [".", ".", ".", "."],
+                [".", ".", ".", "."],
+                [".", ".", ".", "."],
+            ],
+            Player::White,
+        );
+        assert!(matches!(bad_stack, Err(PositionError::StackOrder((0, 0)))));
+    }
+
+    fn moves(notations: &[&str]) -> Vec<GameMove> {
+        notations
+            .iter()
+            .map(|notation| notation.parse().unwrap())
+            .collect()
+    }
+
+    fn play(notations: &[&str]) -> GameState {
+        let mut state = GameState::new();
+        for game_move in moves(notations) {
+            state.make_move(game_move);
+        }
+        state
+    }
+
+    fn play_with(rules: RuleSet, notations: &[&str]) -> GameState {
+        let mut state = GameState::new().with_rules(rules);
+        for game_move in moves(notations) {
+            state.check_move(game_move).unwrap();
+            state.make_move(game_move);
+        }
+        state
+    }
+
+    impl GameState {
+        /// Panic if the position breaks an invariant that move generation or hashing
+        /// relies on: no reserve is negative, no player has more than `NUM_EACH_SIZE`
+        /// pieces of a size between the board and the reserve, and the incrementally
+        /// updated hash and top colors match a full recompute.
+        fn assert_consistent(&self) {
+            for player in [Player::White, Player::Black] {
+                for (size, &count) in self.reserves(player).iter().enumerate() {
+                    assert!(
+                        count >= 0,
+                        "{player:?} has {count} reserve pieces of size {size}"
+                    );
+                    let on_board = self
+                        .board
+                        .contents
+                        .iter()
+                        .flatten()
+                        .filter(|stack| stack.pieces[size] == player.color())
+                        .count() as i32;
+                    assert!(
+                        on_board + count <= NUM_EACH_SIZE,
+                        "{player:?} has {on_board} pieces of size {size} on the board and {count} in reserve"
+                    );
+                }
+            }
+            assert_eq!(
+                self.hash,
+                self.full_zobrist(),
+                "incremental Zobrist hash diverged"
+            );
+            assert_eq!(
+                self.tops,
+                self.full_top_colors(),
+                "incremental top colors diverged"
+            );
+        }
+
+        /// Play up to `plies` random moves from this position `lines` times, checking
+        /// `assert_consistent` after every move and that no move places a size whose
+        /// reserve is exhausted. A cheap way to exercise the reserve bookkeeping of
+        /// `make_move` and `unmake_move` far deeper than a full search could reach.
+        fn check_random_lines(&self, rng: &mut impl Rng, lines: usize, plies: usize) {
+            let mut scratch = self.clone();
+            for _ in 0..lines {
+                let mut played = Vec::new();
+                for _ in 0..plies {
+                    let Some(game_move) = scratch.legal_moves().into_iter().choose(rng) else {
+                        break;
+                    };
+                    if let GameMove::Place { size, .. } = game_move {
+                        assert!(
+                            scratch.reserves(scratch.turn)[size] > 0,
+                            "{game_move} generated with no size {size} pieces in reserve"
+                        );
+                    }
+                    scratch.make_move(game_move);
+                    scratch.assert_consistent();
+                    played.push(game_move);
+                }
+                for game_move in played.into_iter().rev() {
+                    scratch.unmake_move(game_move);
+                }
+                assert_eq!(
+                    scratch.reserves(Player::White),
+                    self.reserves(Player::White),
+                    "reserves not restored by unmake_move"
+                );
+                assert_eq!(
+                    scratch.reserves(Player::Black),
+                    self.reserves(Player::Black),
+                    "reserves not restored by unmake_move"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn zobrist_equal_positions_hash_equal() {
+        let a = play(&["3@a1", "3@d4", "2@b1", "2@c4"]);
+        let b = play(&["2@b1", "2@c4", "3@a1", "3@d4"]);
+        assert_eq!(a.zobrist(), b.zobrist());
+        assert_eq!(GameState::new().zobrist(), GameState::new().zobrist());
+    }
+
+    #[test]
+    fn zobrist_changes_with_a_piece_or_the_turn() {
+        let empty = [["."; BOARD_COLS]; BOARD_ROWS];
+        let mut one_piece = empty;
+        one_piece[1][2] = "W1";
+        let mut other_size = empty;
+        other_size[1][2] = "W2";
+        let hash = |grid, turn| GameState::from_grid(grid, turn).unwrap().zobrist();
+        let base = hash(one_piece, Player::White);
+        assert_ne!(base, hash(empty, Player::White));
+        assert_ne!(base, hash(other_size, Player::White));
+        assert_ne!(base, hash(one_piece, Player::Black));
+    }
+
+    #[test]
+    fn lifting_a_board_piece_offers_the_destinations_from_branch() {
+        let state = play(&["3@b2", "1@c3", "2@a1", "2@c3"]);
+        for source in [(1, 1), (0, 0)] {
+            let mut expected: Vec<Position> = state
+                .branch()
+                .into_iter()
+                .filter_map(|(game_move, _)| match game_move {
+                    GameMove::Move { source: from, dest } if from == source => Some(dest),
+                    _ => None,
+                })
+                .collect();
+            let pending = PendingMove::lift(&state, Source::Board(source)).unwrap();
+            let mut destinations = pending.destinations().to_vec();
+            expected.sort();
+            destinations.sort();
+            assert_eq!(destinations, expected);
+            assert_eq!(
+                pending.drop_at(expected[0]),
+                Some(GameMove::Move {
+                    source,
+                    dest: expected[0]
+                })
+            );
+        }
+        assert_eq!(PendingMove::lift(&state, Source::Board((2, 2))), None);
+    }
+
+    #[test]
+    fn shuffles_are_left_out_on_request() {
+        let state = GameState::from_grid(
+            [
+                ["W0|W3", "W1", ".", "."],
+                [".", ".", ".", "."],
+                [".", ".", ".", "."],
+                [".", ".", ".", "B3"],
+            ],
+            Player::White,
+        )
+        .unwrap();
+        let shuffle: GameMove = "a1-b1".parse().unwrap();
+        let relocation: GameMove = "a1-c1".parse().unwrap();
+        let all = state.legal_moves();
+        assert!(all.contains(&shuffle) && all.contains(&relocation));
+        let options = MoveOptions {
+            exclude_shuffles: true,
+            ..MoveOptions::default()
+        };
+        let filtered = state.legal_moves_with(&options);
+        assert!(!filtered.contains(&shuffle));
+        assert!(filtered.contains(&relocation));
+        assert_eq!(filtered.len(), all.len() - 1);
+        // A relocation from an empty cell is not a shuffle.
+        assert!(!state.is_shuffle("c2-b1".parse().unwrap()));
+    }
+
+    #[test]
+    fn notation_applies_moves_and_tells_errors_apart() {
+        let mut state = GameState::new();
+        for notation in ["3@a1", "0@b2", "2@b2", "3@d4", "a1-c1"] {
+            state.apply_notation(notation).unwrap();
+        }
+        let expected = GameState::from_grid(
+            [
+                [".", ".", "W3", "."],
+                [".", "b0|W2", ".", "."],
+                [".", ".", ".", "."],
+                [".", ".", ".", "B3"],
+            ],
+            Player::Black,
+        )
+        .unwrap();
+        assert_eq!(state.position_string(), expected.position_string());
+        assert_eq!(state.turn, Player::Black);
+
+        assert!(matches!(
+            state.apply_notation("9@z9"),
+            Err(ApplyError::Notation(_))
+        ));
+        assert!(matches!(
+            state.apply_notation("0@c1"),
+            Err(ApplyError::Illegal(_))
+        ));
+        assert_eq!(state.position_string(), expected.position_string());
+    }
+
+    #[test]
+    fn white_perspective_scores_are_positive_when_white_is_better() {
+        // White tops two diagonal cells with pieces Black can't cover.
+        let mut node = Node::new(play(&["3@b2", "1@c3", "3@c3"]));
+        assert!(node.white_perspective_score() > 0);
+        node.branch(1);
+        assert!(node.white_perspective_score() > 0, "{:?}", node.score);
+
+        // White completes the a file next move.
+        let mut winning = Node::new(play(&["3@a1", "0@d4", "3@a2", "0@d3", "3@a3", "0@c4"]));
+        winning.branch(2);
+        assert_eq!(winning.score, Score::WhiteFavored(1));
+        assert_eq!(winning.white_perspective_score(), i32::MAX - 1);
+    }
+
+    #[test]
+    fn the_search_scores_a_repetition_in_the_tree_as_a_draw() {
+        // Black can take c4 back to d4, repeating the position after 3@d4.
+        let mut state = GameState::new();
+        for game_move in moves(&["2@b2", "0@b4", "3@a1", "3@d4", "a1-b1", "d4-c4", "b1-a1"]) {
+            state.make_move(game_move);
+        }
+        let back: GameMove = "c4-d4".parse().unwrap();
+        assert!(!state.is_repetition_draw());
+
+        let mut repeated = state.clone();
+        repeated.make_move(back);
+        assert!(repeated.is_repetition_draw());
+        let draw = alpha_beta(&mut repeated, 0, Score::MIN, Score::MAX, None);
+        assert_eq!(draw, Score::Balanced(0));
+
+        // Without the history there is nothing to repeat.
+        let mut fresh = GameState::from_position(&state.position_string(), Player::Black).unwrap();
+        fresh.make_move(back);
+        assert!(!fresh.is_repetition_draw());
+        let score = alpha_beta(&mut fresh, 0, Score::MIN, Score::MAX, None);
+        assert_eq!(score, fresh.raw_score());
+        assert_ne!(score, Score::Balanced(0));
+    }
+
+    #[test]
+    fn reversing_the_last_relocation_is_not_generated_under_the_rule() {
+        let line = ["3@a1", "3@d4", "a1-b1", "d4-c4"];
+        let reversal: GameMove = "b1-a1".parse().unwrap();
+        let forbid = RuleSet {
+            forbid_reversal: true,
+            ..RuleSet::default()
+        };
+        let mut state = GameState::new().with_rules(forbid);
+        for game_move in moves(&line) {
+            state.apply_move(game_move);
+        }
+        assert!(!state.legal_moves().contains(&reversal));
+        assert!(state
+            .branch()
+            .iter()
+            .all(|&(game_move, _)| game_move != reversal));
+        assert!(state.legal_moves().contains(&"b1-c1".parse().unwrap()));
+
+        let state = play(&line);
+        assert!(state.legal_moves().contains(&reversal));
+    }
+
+    #[test]
+    fn random_positions_are_consistent_and_reproducible() {
+        for target_ply in [0, 5, 10, 20] {
+            let first = GameState::random_position(&mut StdRng::seed_from_u64(11), target_ply);
+            let second = GameState::random_position(&mut StdRng::seed_from_u64(11), target_ply);
+            first.assert_consistent();
+            assert_eq!(first.position_string(), second.position_string());
+            assert_eq!(first.zobrist(), second.zobrist());
+            assert!(matches!(first.raw_score(), Score::Balanced(_)));
+        }
+    }
+
+    #[test]
+    fn per_source_moves_add_up_to_the_legal_moves() {
+        let mut rng = StdRng::seed_from_u64(13);
+        for target_ply in [0, 3, 8, 15] {
+            let state = GameState::random_position(&mut rng, target_ply);
+            let mut per_source: Vec<GameMove> = (0..NUM_SIZES)
+                .flat_map(|size| state.moves_from_reserve(size))
+                .chain(
+                    (0..BOARD_ROWS)
+                        .flat_map(|row| (0..BOARD_COLS).map(move |col| (row, col)))
+                        .flat_map(|position| state.moves_from_board(position)),
+                )
+                .collect();
+            let mut all = state.legal_moves();
+            per_source.sort_by_key(GameMove::to_string);
+            all.sort_by_key(GameMove::to_string);
+            assert_eq!(per_source, all, "{}", state.position_string());
+        }
+    }
+
+    #[test]
+    fn iterative_search_matches_the_recursive_search() {
+        let mut rng = StdRng::seed_from_u64(17);
+        for target_ply in [0, 4, 9] {
+            let state = GameState::random_position(&mut rng, target_ply);
+            for depth in 1..=3 {
+                let mut recursive = state.clone();
+                let mut iterative = state.clone();
+                assert_eq!(
+                    alpha_beta(&mut recursive, depth, Score::MIN, Score::MAX, None,),
+                    alpha_beta_iterative(&mut iterative, depth, Score::MIN, Score::MAX,),
+                );
+                let recursive = MinimaxEngine::new(depth)
+                    .search_root(&state, depth)
+                    .unwrap();
+                let iterative = MinimaxEngine::new(depth)
+                    .with_iterative_search()
+                    .search_root(&state, depth)
+                    .unwrap();
+                assert_eq!(
+                    (recursive.best_move, recursive.score),
+                    (iterative.best_move, iterative.score),
+                    "{}",
+                    state.position_string()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn huge_balanced_scores_never_look_decisive() {
+        for (extreme, clamped) in [
+            (i32::MAX, Score::MAX_BALANCED),
+            (i32::MIN, -Score::MAX_BALANCED),
+        ] {
+            let score = Score::balanced(extreme);
+            assert_eq!(score, Score::Balanced(clamped));
+            assert!(score < Score::WhiteFavored(Score::MAX_WIN_PLIES));
+            assert!(score > Score::BlackFavored(Score::MAX_WIN_PLIES));
+
+            let mut node = Node::new(GameState::new());
+            node.score = score;
+            assert!(node.white_perspective_score() > i32::MIN);
+            assert!(node.white_perspective_score() < i32::MAX);
+        }
+    }
+
+    #[test]
+    fn only_larger_sizes_in_stock_are_playable_on_a_covered_cell() {
+        let state = play(&["1@b2"]);
+        assert_eq!(state.playable_sizes((1, 1)), vec![2, 3]);
+        assert_eq!(state.playable_sizes((0, 0)), vec![0, 1, 2, 3]);
+
+        // Black's size 3 pieces are all on the board.
+        let state =
+            GameState::from_position("W1,B3,B3,./.,.,.,./.,.,.,B3/.,.,.,.", Player::Black).unwrap();
+        assert_eq!(state.playable_sizes((0, 0)), vec![2]);
+    }
+
+    #[test]
+    fn equal_positions_share_a_short_id() {
+        let first = play(&["3@a1", "0@d4", "2@b2"]);
+        let transposed = play(&["2@b2", "0@d4", "3@a1"]);
+        assert_eq!(first.position_string(), transposed.position_string());
+        assert_eq!(first.short_id(), transposed.short_id());
+        let other = play(&["3@a1"]);
+        assert_ne!(first.short_id(), other.short_id());
+        for state in [first, other, GameState::new()] {
+            let id = state.short_id();
+            assert_eq!(id.len(), 11);
+            assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+        }
+    }
+
+    #[test]
+    fn each_move_error_explains_itself() {
+        let three_big =
+            GameState::from_position("W3,W3,W3,./.,.,.,./.,.,.,./.,.,.,.", Player::White).unwrap();
+        let off_board = GameMove::Place {
+            size: 0,
+            dest: (BOARD_ROWS, 0),
+        };
+        let cases = [
+            (
+                GameState::new(),
+                off_board,
+                MoveError::OutOfBounds(off_board),
+                "is off the board",
+            ),
+            (
+                play(&["3@a1"]),
+                "3@a1".parse().unwrap(),
+                MoveError::DestinationFull((0, 0)),
+                "the stack at a1 is full",
+            ),
+            (
+                play(&["1@a1"]),
+                "0@a1".parse().unwrap(),
+                MoveError::PieceTooSmall { needed: 2, have: 0 },
+                "a size 0 piece can't cover that cell; it needs size 2 or larger",
+            ),
+            (
+                three_big,
+                "3@d4".parse().unwrap(),
+                MoveError::NoReserve {
+                    player: Player::White,
+                    size: 3,
+                },
+                "White has no size 3 pieces left in reserve",
+            ),
+            (
+                GameState::new(),
+                "b2-c3".parse().unwrap(),
+                MoveError::EmptySource((1, 1)),
+                "there is no piece at b2",
+            ),
+            (
+                play(&["3@a1", "0@d4"]),
+                "a1-a1".parse().unwrap(),
+                MoveError::SamePosition((0, 0)),
+                "the piece at a1 must move somewhere else",
+            ),
+        ];
+        for (state, game_move, expected, message) in cases {
+            let error = state.check_move(game_move).unwrap_err();
+            assert_eq!(error, expected);
+            assert!(error.to_string().contains(message), "{error}");
+        }
+    }
+
+    #[test]
+    fn the_greedy_engine_gobbles_when_it_gains() {
+        let state =
+            GameState::from_position(".,.,.,./.,B0,.,./.,.,B1,./.,.,.,.", Player::White).unwrap();
+        for randomize_ties in [false, true] {
+            let mut engine = GreedyEngine::new(StdRng::seed_from_u64(0), randomize_ties);
+            let game_move = engine.choose_move(&state).unwrap();
+            assert!(state.check_move(game_move).is_ok());
+            let (row, col) = game_move.dest().unwrap();
+            assert_eq!(
+                state.board.contents[row][col].top_color(),
+                Color::Black,
+                "{game_move}"
+            );
+            let mut after = state.clone();
+            after.make_move(game_move);
+            assert!(after.raw_score() > state.raw_score());
+        }
+    }
+
+    #[test]
+    fn three_by_four_boards_win_on_rows_columns_and_fitting_diagonals() {
+        let lines = |win_length, diagonal_wins| -> Vec<Vec<Position>> {
+            win_segments(3, 4, win_length, diagonal_wins)
+                .map(|segment| segment.cells().collect())
+                .collect()
+        };
+
+        // Four in a row: whole rows and whole (three-cell) columns, no diagonal fits.
+        let four = lines(4, true);
+        assert_eq!(four.len(), 3 + 4);
+        assert!(four.contains(&vec![(1, 0), (1, 1), (1, 2), (1, 3)]));
+        assert!(four.contains(&vec![(0, 2), (1, 2), (2, 2)]));
+        assert!(four.iter().all(|line| line.len() == 4 || line.len() == 3));
+
+        // Three in a row: diagonals fit, and count only when turned on.
+        let three = lines(3, true);
+        assert_eq!(three.len(), 3 * 2 + 4 + 4);
+        assert!(three.contains(&vec![(0, 1), (1, 2), (2, 3)]));
+        assert!(three.contains(&vec![(0, 2), (1, 1), (2, 0)]));
+        let no_diagonals = lines(3, false);
+        assert_eq!(no_diagonals, three[..3 * 2 + 4].to_vec());
+    }
+
+    #[test]
+    fn phases_follow_the_pieces_on_the_board() {
+        assert_eq!(GameState::new().phase(), Phase::Opening);
+        let midgame = play(&["0@a1", "0@d4", "1@b2", "1@c3", "2@a4", "2@d1"]);
+        assert_eq!(midgame.phase(), Phase::Midgame);
+        let full = GameState::from_position(
+            "W0,W1,W2,W3/B0,B1,B2,B3/W0,W1,W2,W3/B0,B1,B2,B3",
+            Player::White,
+        )
+        .unwrap();
+        assert_eq!(full.phase(), Phase::Endgame);
+
+        let thresholds = PhaseThresholds {
+            midgame: 2,
+            endgame: 6,
+        };
+        assert_eq!(play(&["0@a1"]).phase_with(&thresholds), Phase::Opening);
+        assert_eq!(
+            play(&["0@a1", "0@d4"]).phase_with(&thresholds),
+            Phase::Midgame
+        );
+        assert_eq!(midgame.phase_with(&thresholds), Phase::Endgame);
+    }
+
+    #[test]
+    fn is_legal_agrees_with_legal_moves() {
+        let cells: Vec<Position> = (0..BOARD_ROWS)
+            .flat_map(|row| (0..BOARD_COLS).map(move |col| (row, col)))
+            .collect();
+        let mut candidates = Vec::new();
+        for &dest in &cells {
+            candidates.extend((0..NUM_SIZES).map(|size| GameMove::Place { size, dest }));
+            candidates.extend(cells.iter().map(|&source| GameMove::Move { source, dest }));
+        }
+        let mut rng = StdRng::seed_from_u64(17);
+        for target_ply in 0..30 {
+            let state = GameState::random_position(&mut rng, target_ply);
+            let mut legal: Vec<GameMove> = candidates
+                .iter()
+                .copied()
+                .filter(|game_move| state.is_legal(game_move))
+                .collect();
+            let mut generated = state.legal_moves();
+            legal.sort_by_key(GameMove::to_string);
+            generated.sort_by_key(GameMove::to_string);
+            assert_eq!(legal, generated, "{}", state.position_string());
+        }
+    }
+
+    #[test]
+    fn move_accessors_cover_placements_relocations_and_passes() {
+        let state = play(&["2@b2", "0@d4"]);
+        let place: GameMove = "3@a1".parse().unwrap();
+        assert_eq!(place.dest(), Some((0, 0)));
+        assert_eq!(place.source(), None);
+        assert_eq!(place.size(&state.board), Some(3));
+
+        let relocation: GameMove = "b2-c3".parse().unwrap();
+        assert_eq!(relocation.dest(), Some((2, 2)));
+        assert_eq!(relocation.source(), Some((1, 1)));
+        assert_eq!(relocation.size(&state.board), Some(2));
+
+        assert_eq!(GameMove::Pass.dest(), None);
+        assert_eq!(GameMove::Pass.source(), None);
+        assert_eq!(GameMove::Pass.size(&state.board), None);
+    }
+
+    #[test]
+    fn stuck_children_are_scored_as_draws() {
+        // Whatever White places, Black is left with no move.
+        let mut state = GameState::with_reserves([0, 0, 0, 1], [0; NUM_SIZES]);
+        let mut child = state.clone();
+        child.make_move("3@b2".parse().unwrap());
+        assert_eq!(child.result(), Some(GameResult::Draw));
+        assert_ne!(EvalWeights::default().evaluate(&child), Score::Balanced(0));
+        let draw = GameResult::Draw.score();
+        assert_eq!(
+            alpha_beta(&mut state, 2, Score::MIN, Score::MAX, None),
+            draw
+        );
+        assert_eq!(
+            alpha_beta_iterative(&mut state, 2, Score::MIN, Score::MAX),
+            draw
+        );
+        assert_eq!(MinimaxEngine::new(2).search(&child).unwrap().score, draw);
+    }
+
+    #[test]
+    fn alpha_beta_with_a_table_matches_alpha_beta_without() {
+        let mut rng = StdRng::seed_from_u64(19);
+        let mut tt = TranspositionTable::new(1 << 14, ReplacementPolicy::default());
+        for target_ply in [2, 6, 10, 14] {
+            let random = GameState::random_position(&mut rng, target_ply);
+            // A fresh history, so no line can run into a repetition draw.
+            let mut state =
+                GameState::from_position(&random.position_string(), random.turn).unwrap();
+            let expected = alpha_beta(&mut state, 3, Score::MIN, Score::MAX, None);
+            let Score::Balanced(value) = expected else {
+                continue;
+            };
+            let (below, above) = (Score::Balanced(value - 1), Score::Balanced(value + 1));
+            // Narrow windows fill the table with bounds before the full search.
+            for (alpha, beta) in [(below, above), (Score::MIN, below), (above, Score::MAX)] {
+                alpha_beta(&mut state, 3, alpha, beta, Some(&mut tt));
+            }
+            let with_table = alpha_beta(&mut state, 3, Score::MIN, Score::MAX, Some(&mut tt));
+            assert_eq!(with_table, expected, "{}", state.position_string());
+        }
+    }
+
+    #[test]
+    fn iterative_deepening_generates_each_nodes_moves_once() {
+        let children = |node: &Node| match node.state {
+            NodeState::Branches(ref branches) => branches.as_ptr(),
+            _ => panic!("node was not expanded"),
+        };
+        let mut node = Node::new(GameState::new());
+        node.branch(1);
+        let first = children(&node);
+        for depth in 2..=3 {
+            node.branch(depth);
+            // The same children, not a regenerated list.
+            assert_eq!(children(&node), first);
+        }
+
+        let mut direct = Node::new(GameState::new());
+        direct.branch(3);
+        assert_eq!(node.score, direct.score);
+    }
+
+    #[test]
+    fn top_counts_count_the_cells_each_color_tops() {
+        assert_eq!(GameState::new().top_counts(), (0, 0));
+        let state =
+            GameState::from_position("W3,B0|W1,.,./.,W0|B2,B1,./.,.,.,./.,.,.,B3", Player::White)
+                .unwrap();
+        assert_eq!(state.top_counts(), (2, 3));
+    }
+
+    #[test]
+    fn dead_draws_need_every_line_out_of_reach() {
+        let play_from = |stock: i32, line: &[&str]| {
+            let stock = [stock, 0, 0, 0];
+            let mut state = GameState::with_reserves(stock, stock);
+            for game_move in moves(line) {
+                state.make_move(game_move);
+            }
+            state
+        };
+        // Three pieces each can never fill a line of four.
+        let line = ["0@b4", "0@b1", "0@b3", "0@c2", "0@a4", "0@d4"];
+        let locked = play_from(3, &line);
+        assert!(locked.is_dead_draw());
+
+        // Pieces left in reserve keep the game live.
+        let unfinished = play_from(3, &line[..5]);
+        assert!(!unfinished.is_dead_draw());
+
+        // With four pieces each, White completes row 1 by moving a3 to d1.
+        let live = play_from(
+            4,
+            &[
+                "0@a1", "0@d4", "0@b1", "0@c3", "0@c1", "0@b4", "0@a3", "0@d2",
+            ],
+        );
+        assert!(!live.is_dead_draw());
+    }
+
+    #[test]
+    fn diagonal_cells_score_like_edges_without_diagonal_wins() {
+        let no_diagonals = RuleSet {
+            diagonal_wins: false,
+            ..RuleSet::default()
+        };
+        let corner = play_with(no_diagonals, &["0@a1"]);
+        let edge = play_with(no_diagonals, &["0@b1"]);
+        assert_eq!(corner.raw_score(), edge.raw_score());
+        assert_eq!(
+            corner.raw_score(),
+            Score::Balanced(EvalWeights::default().cell)
+        );
+
+        let corner = play(&["0@a1"]);
+        let edge = play(&["0@b1"]);
+        assert!(corner.raw_score() > edge.raw_score());
+        assert_eq!(
+            corner.raw_score(),
+            Score::Balanced(EvalWeights::default().diagonal_cell)
+        );
+    }
+
+    #[test]
+    fn every_cell_on_a_shorter_diagonal_scores_as_diagonal() {
+        let three_in_a_row = RuleSet {
+            win_length: 3,
+            ..RuleSet::default()
+        };
+        let diagonal_cell = Score::Balanced(EvalWeights::default().diagonal_cell);
+        // Off the main diagonals, but on a three-cell diagonal.
+        for notation in ["0@a1", "0@b1", "0@a2"] {
+            let state = play_with(three_in_a_row, &[notation]);
+            assert_eq!(state.raw_score(), diagonal_cell, "{notation}");
+        }
+    }
+
+    #[test]
+    fn transformed_moves_stay_legal_in_transformed_positions() {
+        let mut rng = StdRng::seed_from_u64(23);
+        for target_ply in [0, 4, 9, 14] {
+            let state = GameState::random_position(&mut rng, target_ply);
+            let legal = state.legal_moves();
+            for (symmetry, transformed) in state.symmetries() {
+                let mut expected: Vec<GameMove> = legal
+                    .iter()
+                    .map(|game_move| game_move.transform(symmetry))
+                    .collect();
+                for &game_move in &expected {
+                    assert!(transformed.is_legal(&game_move), "{symmetry:?} {game_move}");
+                }
+                let mut generated = transformed.legal_moves();
+                expected.sort_by_key(GameMove::to_string);
+                generated.sort_by_key(GameMove::to_string);
+                assert_eq!(generated, expected, "{symmetry:?}");
+            }
+        }
+        let state = play(&["3@a1"]);
+        assert_eq!(
+            state.rotate90().position_string(),
+            state.transform(Symmetry::Rotate90).position_string()
+        );
+        assert_ne!(state.rotate90().position_string(), state.position_string());
+        assert_eq!(
+            state
+                .reflect_horizontal()
+                .reflect_horizontal()
+                .position_string(),
+            state.position_string()
+        );
+    }
+
+    #[test]
+    fn a_won_position_has_no_moves() {
+        let won = play(&["3@a1", "0@d4", "3@a2", "0@d3", "3@a3", "0@c4", "2@a4"]);
+        assert_eq!(won.winner(), Some(Player::White));
+        assert!(won.legal_moves().is_empty());
+        assert!(won.branch().is_empty());
+        assert_eq!(won.legal_move_count(), 0);
+        assert_eq!(perft(&won, 2), 0);
+    }
+
+    #[test]
+    fn engines_built_from_a_config_search_to_its_depth() {
+        let config = EngineConfig::default()
+            .with_depth(2)
+            .with_transposition_table(1 << 10, ReplacementPolicy::default());
+        let mut engine = MinimaxEngine::from_config(config);
+        assert_eq!(engine.config(), &config);
+        assert!(engine.transposition_table.is_some());
+        let state = play(&["3@b2"]);
+        let result = engine.search(&state).unwrap();
+        assert_eq!(result.depth_reached, 2);
+        assert_eq!(result.pv.len(), 2);
+
+        let mut deeper = MinimaxEngine::from_config(config.with_depth(3));
+        assert_eq!(deeper.search(&state).unwrap().depth_reached, 3);
+    }
+
+    #[test]
+    fn the_threat_overlay_marks_both_lines_of_a_fork() {
+        // White is one cell from row 1 (at d1) and from the a file (at a4).
+        let state =
+            GameState::from_position("W3,W0,W0,./W1,.,.,./W1,.,.,./.,.,.,B3", Player::Black)
+                .unwrap();
+        let overlay = state.threat_overlay();
+        for (row, cells) in overlay.iter().enumerate() {
+            for (col, info) in cells.iter().enumerate() {
+                let on_fork = row == 0 || col == 0;
+                assert_eq!(info.white, on_fork, "({row}, {col})");
+                assert!(!info.black, "({row}, {col})");
+            }
+        }
+    }
+
+    #[test]
+    fn undo_last_restores_the_board_and_reserves() {
+        let one_move = play(&["3@a1"]);
+        let mut state = play(&["3@a1", "3@d4", "a1-b2"]);
+        assert_eq!(state.undo_last(), Some("a1-b2".parse().unwrap()));
+        assert_eq!(state.undo_last(), Some("3@d4".parse().unwrap()));
+        assert_eq!(state.position_string(), one_move.position_string());
+        assert_eq!(
+            state.reserves(Player::White),
+            one_move.reserves(Player::White)
+        );
+        assert_eq!(
+            state.reserves(Player::Black),
+            one_move.reserves(Player::Black)
+        );
+        assert_eq!(state.zobrist(), one_move.zobrist());
+        assert_eq!(state.undo_last(), Some("3@a1".parse().unwrap()));
+        assert_eq!(state.undo_last(), None);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "was not the last move made with make_move")]
+    fn unmaking_an_applied_move_panics_in_debug_builds() {
+        let mut state = GameState::new();
+        let game_move = "3@a1".parse().unwrap();
+        state.apply_move(game_move);
+        state.unmake_move(game_move);
+    }
+
+    #[test]
+    fn score_helpers_cover_every_variant() {
+        let white = Score::WhiteFavored(3);
+        let black = Score::BlackFavored(0);
+        let balanced = Score::Balanced(-7);
+        assert!(white.is_decisive() && black.is_decisive() && !balanced.is_decisive());
+        assert_eq!(white.winner(), Some(Player::White));
+        assert_eq!(black.winner(), Some(Player::Black));
+        assert_eq!(balanced.winner(), None);
+        assert!(white.is_win_for(Player::White) && !white.is_win_for(Player::Black));
+        assert!(black.is_win_for(Player::Black) && !black.is_win_for(Player::White));
+        assert!(!balanced.is_win_for(Player::White) && !balanced.is_win_for(Player::Black));
+    }
+
+    #[test]
+    fn the_hybrid_engine_plays_a_mate_in_one() {
+        let state = play(&["3@a1", "0@d4", "3@a2", "0@d3", "3@a3", "0@c4"]);
+        for minimax_depth in [1, 2] {
+            let mut engine = HybridEngine::new(minimax_depth, 100, StdRng::seed_from_u64(0));
+            let game_move = engine.choose_move(&state).unwrap();
+            let mut after = state.clone();
+            after.try_apply(game_move).unwrap();
+            assert_eq!(after.winner(), Some(Player::White), "{game_move}");
+        }
+    }
+
+    #[test]
+    fn tie_breaks_pick_the_same_move_every_run() {
+        // Covering b2 with either larger piece is equally good for Black.
+        let state = play(&["1@b2"]);
+        let choose = |tie_break| {
+            let config = EngineConfig::default()
+                .with_depth(1)
+                .with_tie_break(tie_break);
+            MinimaxEngine::from_config(config)
+                .choose_move(&state)
+                .unwrap()
+        };
+        for tie_break in [
+            TieBreak::FirstGenerated,
+            TieBreak::MostCentral,
+            TieBreak::MostAggressive,
+            TieBreak::Random(5),
+            TieBreak::Swindle,
+        ] {
+            assert_eq!(choose(tie_break), choose(tie_break), "{tie_break:?}");
+            assert_eq!(choose(tie_break).dest(), Some((1, 1)), "{tie_break:?}");
+        }
+        let first = state
+            .legal_moves()
+            .into_iter()
+            .find(|game_move| game_move.dest() == Some((1, 1)))
+            .unwrap();
+        assert_eq!(choose(TieBreak::FirstGenerated), first);
+    }
+
+    #[test]
+    fn move_lists_build_the_position_they_lead_to() {
+        let state = GameState::from_move_list(
+            &moves(&["3@a1", "0@b2", "2@b2", "3@d4", "a1-c1"]),
+            Player::White,
+        )
+        .unwrap();
+        let expected =
+            GameState::from_position(".,.,W3,./.,b0|W2,.,./.,.,.,./.,.,.,B3", Player::Black)
+                .unwrap();
+        assert_eq!(state.position_string(), expected.position_string());
+        for player in [Player::White, Player::Black] {
+            assert_eq!(state.reserves(player), expected.reserves(player));
+        }
+        assert_eq!(
+            GameState::from_move_list(&moves(&["3@a1", "3@a1"]), Player::White).err(),
+            Some((1, MoveError::DestinationFull((0, 0))))
+        );
+    }
+
+    #[test]
+    fn cached_evaluations_match_uncached_ones() {
+        let weights = EvalWeights {
+            cell: 5,
+            ..EvalWeights::default()
+        };
+        // Few slots, so patterns collide and replace each other.
+        let mut cache = EvalCache::new(7, weights);
+        let mut rng = StdRng::seed_from_u64(29);
+        for target_ply in (0..40).map(|ply| ply % 20) {
+            let state = GameState::random_position(&mut rng, target_ply);
+            for _ in 0..2 {
+                assert_eq!(
+                    state.raw_score_cached(&mut cache),
+                    state.raw_score_with(&weights),
+                    "{}",
+                    state.position_string()
+                );
+            }
+        }
+
+        // The same top colors over different covered pieces share an entry.
+        let covered =
+            GameState::from_position("W0|B1,.,.,./.,.,.,./.,.,.,./.,.,.,.", Player::White).unwrap();
+        let bare =
+            GameState::from_position("B1,.,.,./.,.,.,./.,.,.,./.,.,.,.", Player::White).unwrap();
+        assert_eq!(
+            covered.raw_score_cached(&mut cache),
+            covered.raw_score_with(&weights)
+        );
+        assert_eq!(
+            bare.raw_score_cached(&mut cache),
+            bare.raw_score_with(&weights)
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "apply_move: Move { source: (0, 0), dest: (0, 4) } is off the board")]
+    fn applying_an_off_board_move_panics_in_debug_builds() {
+        let mut state = play(&["3@a1", "0@d4"]);
+        state.apply_move(GameMove::Move {
+            source: (0, 0),
+            dest: (0, BOARD_COLS),
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "apply_move: Place { size: 4, dest: (0, 0) } is off the board")]
+    fn applying_a_size_that_does_not_exist_panics_in_debug_builds() {
+        GameState::new().apply_move(GameMove::Place {
+            size: NUM_SIZES,
+            dest: (0, 0),
+        });
+    }
+
+    #[test]
+    fn the_walker_visits_every_expanded_node() {
+        let state = play(&["3@b2", "1@c3"]);
+        let count = |node: &Node| {
+            let mut nodes = 0;
+            let mut deepest = 0;
+            node.walk(&mut |_, _, depth| {
+                nodes += 1;
+                deepest = deepest.max(depth);
+            });
+            (nodes, deepest)
+        };
+
+        let mut node = Node::new(state.clone());
+        node.branch(1);
+        assert_eq!(count(&node), (perft(&state, 1), 1));
+
+        node.branch(2);
+        assert_eq!(count(&node), (perft(&state, 1) + perft(&state, 2), 2));
+    }
+
+    #[test]
+    fn zugzwang_when_every_move_leaves_a_diagonal() {
+        // With the reserves empty, White can only move a piece off a diagonal cell
+        // to an empty edge cell: its pieces are too small to cover Black's.
+        let position = "W0,B1,.,W0/B1,W0,B2,./.,B2,B2,./W1,.,.,B1";
+        let weights = EvalWeights::default();
+        let out_of_reserves = |turn| {
+            let mut state = GameState::from_position(position, turn).unwrap();
+            state.white_pieces = [0; NUM_SIZES];
+            state.black_pieces = [0; NUM_SIZES];
+            state.resync();
+            state
+        };
+        let white = out_of_reserves(Player::White);
+        assert!(!white.legal_moves().is_empty());
+        assert!(white.all_moves_worsen(&weights));
+
+        // Black can move an edge piece to another edge cell at no cost.
+        let black = out_of_reserves(Player::Black);
+        assert!(!black.all_moves_worsen(&weights));
+
+        // A decided game has no moves to worsen it.
+        let won = play(&["3@a1", "0@d4", "3@a2", "0@d3", "3@a3", "0@c4", "2@a4"]);
+        assert!(!won.all_moves_worsen(&weights));
+    }
+
+    #[test]
+    fn only_the_smallest_size_in_stock_goes_on_empty_cells_on_request() {
+        // White has placed its size 0 pieces, so size 1 is the smallest in stock.
+        let state = play(&["0@a1", "3@d4", "0@b1", "3@d3", "0@c1", "2@b2"]);
+        let options = MoveOptions {
+            smallest_on_empty: true,
+            ..MoveOptions::default()
+        };
+        let filtered = state.legal_moves_with(&options);
+        for game_move in state.legal_moves() {
+            let GameMove::Place { size, dest } = game_move else {
+                assert!(filtered.contains(&game_move));
+                continue;
+            };
+            let kept = filtered.contains(&game_move);
+            if state.board().stack(dest).top() == 0 {
+                assert_eq!(kept, size == 1, "{game_move}");
+            } else {
+                assert!(kept, "{game_move}");
+            }
+        }
+    }
+
+    #[test]
+    fn stack_contents_run_bottom_to_top() {
+        let state = play(&["0@b2", "1@b2", "3@b2"]);
+        assert_eq!(
+            state.stack_at((1, 1)).contents(),
+            vec![(Color::White, 0), (Color::Black, 1), (Color::White, 3)]
+        );
+        assert!(state.stack_at((0, 0)).contents().is_empty());
+    }
+
+    #[test]
+    fn reserve_pieces_only_go_on_empty_cells_when_they_cannot_gobble() {
+        let rules = RuleSet {
+            reserve_can_gobble: false,
+            ..RuleSet::default()
+        };
+        let line = ["1@b2", "0@c3"];
+        let state = play_with(rules, &line);
+        let onto_occupied = |state: &GameState| {
+            state
+                .legal_moves()
+                .into_iter()
+                .filter(|game_move| {
+                    matches!(game_move, GameMove::Place { dest, .. } if state.stack_at(*dest).top() > 0)
+                })
+                .count()
+        };
+        assert_eq!(onto_occupied(&state), 0);
+        assert_eq!(
+            state.check_move("3@c3".parse().unwrap()),
+            Err(MoveError::ReserveCannotGobble((2, 2)))
+        );
+        assert!(state.legal_moves().contains(&"b2-c3".parse().unwrap()));
+
+        assert!(onto_occupied(&play(&line)) > 0);
+    }
+
+    #[test]
+    fn pinned_pieces_are_penalized() {
+        let weights = EvalWeights {
+            pinned_piece: 5,
+            ..EvalWeights::default()
+        };
+        let unweighted = EvalWeights::default();
+        // White's d1 covers the piece that would complete Black's row 1.
+        let pinned =
+            GameState::from_position("B0,B0,B0,B1|W3/.,.,.,./.,.,.,./.,.,.,.", Player::White)
+                .unwrap();
+        // The same pieces, with the covered piece off that row.
+        let free =
+            GameState::from_position("B0,B0,B0,./.,.,.,./.,.,.,B1|W3/.,.,.,.", Player::White)
+                .unwrap();
+        let Score::Balanced(unpenalized) = pinned.raw_score_with(&unweighted) else {
+            panic!("the game isn't decided");
+        };
+        assert_eq!(
+            pinned.raw_score_with(&weights),
+            Score::Balanced(unpenalized - 5)
+        );
+        assert_eq!(
+            free.raw_score_with(&weights),
+            free.raw_score_with(&unweighted)
+        );
+    }
+
+    #[test]
+    fn huge_weights_saturate_without_deciding_the_game() {
+        // White's pinned piece on d1 would complete row 1 if Black's cover moved.
+        let state =
+            GameState::from_position("W2,W2,W2,W1|B3/B2,B2,.,./.,.,.,./.,.,.,.", Player::Black)
+                .unwrap();
+        for extreme in [i32::MIN, i32::MAX] {
+            let weights = EvalWeights {
+                cell: extreme,
+                diagonal_cell: extreme,
+                pinned_piece: extreme,
+            };
+            let score = state.raw_score_with(&weights);
+            assert!(!score.is_decisive(), "{score:?}");
+            assert_eq!(
+                score,
+                state.raw_score_cached(&mut EvalCache::new(16, weights))
+            );
+        }
+    }
+
+    #[test]
+    fn search_results_are_internally_consistent() {
+        let state = play(&["3@b2", "1@c3", "2@a1"]);
+        let mut engine = MinimaxEngine::new(3);
+        let result = engine.search(&state).unwrap();
+        assert_eq!(result.depth_reached, 3);
+        assert_eq!(result.pv.first().copied(), result.best_move);
+        assert!(result.pv.len() <= result.depth_reached as usize);
+        assert!(result.stats.nodes > result.pv.len() as u64);
+
+        // The PV is playable, and its first move scores what the search reported.
+        let mut line = state.clone();
+        for &game_move in &result.pv {
+            line.try_apply(game_move).unwrap();
+        }
+        assert_eq!(
+            engine.score_move(&state, result.best_move.unwrap(), 3),
+            result.score
+        );
+    }
+
+    #[test]
+    fn fresh_boards_are_empty_and_stacked_boards_full() {
+        let fresh = GameState::new();
+        assert!(fresh.is_board_empty() && !fresh.is_board_full());
+
+        let occupied = GameState::from_position(
+            "W0,W1,W2,W3/B0,B1,B2,B3/W0,W1,W2,W3/B0,B1,B2,B3",
+            Player::White,
+        )
+        .unwrap();
+        assert!(!occupied.is_board_empty() && !occupied.is_board_full());
+        // Only a largest piece on top fills a cell; there aren't enough of them in a
+        // standard set to cover the board, so top every cell by hand.
+        let mut full = occupied.clone();
+        for stack in full.board.contents.iter_mut().flatten() {
+            stack.pieces[NUM_SIZES - 1] = Color::White;
+        }
+        assert!(!full.is_board_empty() && full.is_board_full());
+    }
+
+    #[test]
+    fn ordered_moves_start_with_the_top_priority_move() {
+        let mut rng = StdRng::seed_from_u64(31);
+        for target_ply in [0, 5, 10, 15] {
+            let state = GameState::random_position(&mut rng, target_ply);
+            let mut sorted = state.legal_moves();
+            sorted.sort_by_key(|&game_move| Reverse(CoverFirst.priority(&state, game_move)));
+            let ordered: Vec<GameMove> = state.ordered_moves(&CoverFirst).collect();
+            assert_eq!(ordered[0], sorted[0]);
+            assert_eq!(ordered, sorted);
+        }
+    }
+
+    #[test]
+    fn turns_alternate_between_the_two_players() {
+        let mut state = GameState::new();
+        let mut rng = StdRng::seed_from_u64(37);
+        for _ in 0..30 {
+            let mover = state.turn;
+            assert_ne!(mover.color(), Color::Empty);
+            let Some((_, child)) = state.random_move(&mut rng) else {
+                break;
+            };
+            assert_eq!(child.turn, mover.other());
+            assert_eq!(child.turn.color(), mover.color().other());
+            state = child;
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Color::other called on Empty")]
+    fn the_other_of_empty_panics_in_debug_builds() {
+        Color::Empty.other();
+    }
+
+    #[test]
+    fn players_and_colors_convert_both_ways() {
+        for player in [Player::White, Player::Black] {
+            assert_eq!(Color::from(player), player.color());
+            assert_eq!(player.color().player(), Some(player));
+            assert_eq!(player.other().other(), player);
+        }
+        assert_eq!(Color::Empty.player(), None);
+
+        // Sample positions behave as before: reserves, winners and scores are
+        // reported per player.
+        let state = play(&["3@a1", "0@d4", "3@a2"]);
+        assert_eq!(state.turn, Player::Black);
+        assert_eq!(state.reserves(Player::White), [3, 3, 3, 1]);
+        assert_eq!(state.reserves(Player::Black), [2, 3, 3, 3]);
+        let won = play(&["3@a1", "0@d4", "3@a2", "0@d3", "3@a3", "0@c4", "2@a4"]);
+        assert_eq!(won.winner(), Some(Player::White));
+        assert_eq!(won.raw_score(), Score::for_player(Player::White));
+        assert_eq!(won.raw_score().winner(), Some(Player::White));
+    }
+
+    #[test]
+    fn parallel_analysis_matches_serial_analysis() {
+        let state = play(&["3@b2", "1@c3", "2@a1", "2@c3"]);
+        let serial = state.analyze(2);
+        for threads in [1, 2, 3, 8] {
+            assert_eq!(state.analyze_parallel(2, threads).unwrap(), serial);
+        }
+    }
+
+    #[test]
+    fn piece_on_top_reports_color_and_size() {
+        let state = play(&["0@b2", "1@b2", "3@a1"]);
+        assert_eq!(state.top_piece((1, 1)), Some((Color::Black, 1)));
+        assert_eq!(state.top_piece((0, 0)), Some((Color::White, 3)));
+        assert_eq!(state.top_piece((3, 3)), None);
+    }
+
+    #[test]
+    fn every_difficulty_plays_a_legal_move() {
+        let state =
+            GameState::from_position("W2,W2,W2,./B2,B2,B2,./.,.,.,./.,.,.,.", Player::White)
+                .unwrap();
+        let mut rng = StdRng::seed_from_u64(3);
+        for difficulty in Difficulty::all() {
+            let game_move = MinimaxEngine::with_difficulty(difficulty, &mut rng)
+                .choose_move(&state)
+                .unwrap();
+            assert_eq!(state.check_move(game_move), Ok(()), "{difficulty:?}");
+        }
+        assert_ne!(Difficulty::Beginner.config(), Difficulty::Expert.config());
+    }
+
+    #[test]
+    fn random_difficulties_are_seeded_from_the_rng() {
+        let seed = |rng_seed: u64| {
+            let mut rng = StdRng::seed_from_u64(rng_seed);
+            match MinimaxEngine::with_difficulty(Difficulty::Beginner, &mut rng)
+                .config()
+                .tie_break
+            {
+                TieBreak::Random(seed) => seed,
+                tie_break => panic!("{tie_break:?}"),
+            }
+        };
+        assert_eq!(seed(1), seed(1));
+        assert_ne!(seed(1), seed(2));
+    }
+
+    #[test]
+    fn the_time_limit_cuts_an_iteration_short() {
+        let limit = Duration::from_millis(100);
+        for config in [
+            EngineConfig::default(),
+            EngineConfig::default().with_iterative_search(),
+        ] {
+            let mut engine =
+                MinimaxEngine::from_config(config.with_depth(12).with_time_limit(limit));
+            let start = Instant::now();
+            let result = engine.search(&GameState::new()).unwrap();
+            // Generous, so a loaded machine running the suite in parallel doesn't fail it.
+            assert!(start.elapsed() < limit * 20, "{:?}", start.elapsed());
+            assert!(result.stats.timed_out);
+            assert!(result.depth_reached < 12);
+            assert!(result.best_move.is_some());
+            assert_eq!(result.pv.len(), result.depth_reached as usize);
+        }
+    }
+
+    #[test]
+    fn the_winning_line_is_reported_with_its_cells() {
+        let row = play(&["3@a1", "0@a4", "3@b1", "0@b4", "3@c1", "0@c4", "2@d1"]);
+        assert_eq!(
+            row.winning_line(),
+            Some((Player::White, vec![(0, 0), (0, 1), (0, 2), (0, 3)]))
+        );
+        let diagonal = play(&["3@a1", "0@a4", "3@b2", "0@b4", "3@c3", "0@d1", "2@d4"]);
+        assert_eq!(
+            diagonal.winning_line(),
+            Some((Player::White, vec![(0, 0), (1, 1), (2, 2), (3, 3)]))
+        );
+        assert_eq!(play(&["3@a1"]).winning_line(), None);
+    }
+
+    #[test]
+    fn decisive_scores_prefer_the_nearer_win() {
+        assert!(Score::WhiteFavored(1) > Score::WhiteFavored(3));
+        assert!(Score::BlackFavored(1) < Score::BlackFavored(3));
+        assert!(Score::WhiteFavored(Score::MAX_WIN_PLIES) > Score::balanced(i32::MAX));
+        assert!(Score::BlackFavored(Score::MAX_WIN_PLIES) < Score::balanced(i32::MIN));
+        let flattened = [
+            Score::MIN,
+            Score::BlackFavored(5),
+            Score::balanced(i32::MIN),
+            Score::Balanced(0),
+            Score::balanced(i32::MAX),
+            Score::WhiteFavored(5),
+            Score::MAX,
+        ]
+        .map(|score| score.white_perspective());
+        assert!(flattened.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(flattened[0], i32::MIN);
+        assert_eq!(flattened[5], i32::MAX - 5);
+    }
+
+    #[test]
+    fn a_lost_engine_holds_out_as_long_as_it_can() {
+        // White to move loses, and most moves, like 0@a1, lose at once.
+        let state = play(&[
+            "3@d3", "1@d1", "0@a2", "1@a2", "2@b3", "2@b1", "2@a3", "3@b2", "1@c4", "3@b3",
+        ]);
+        let mut engine =
+            MinimaxEngine::new(4).with_transposition_table(1 << 16, ReplacementPolicy::default());
+        let fast_loss = engine.score_move(&state, "0@a1".parse().unwrap(), 4);
+        assert_eq!(fast_loss, Score::BlackFavored(2));
+        let result = engine.search(&state).unwrap();
+        assert_eq!(result.score, Score::BlackFavored(4));
+        let slow_losses = moves(&["3@b1", "d3-b1", "3@b4", "d3-b4"]);
+        assert!(slow_losses.contains(&result.best_move.unwrap()));
+    }
+
+    #[test]
+    fn critical_moves_leave_out_quiet_moves() {
+        // White wins by covering a4; far from either line, 0@c2 changes nothing.
+        let state = GameState::from_position("W3,.,.,./W3,.,.,./W3,.,.,./.,.,B0,B0", Player::White)
+            .unwrap();
+        let critical: Vec<GameMove> = state.critical_moves().collect();
+        assert!(critical.contains(&"2@a4".parse().unwrap()));
+        assert!(!critical.contains(&"0@c2".parse().unwrap()));
+        assert!(critical.len() < state.legal_move_count());
+        for game_move in state.legal_moves() {
+            let mut after = state.clone();
+            after.make_move(game_move);
+            if after.winner().is_some() {
+                assert!(critical.contains(&game_move), "{game_move}");
+            }
+        }
+    }
+
+    #[test]
+    fn gobble_counts_only_count_pieces_directly_on_the_opponent() {
+        let state = GameState::from_position(
+            "W0|B1|W3,B0|W2,W0|W1,./W1|B2,B0,.,./.,.,.,./.,.,.,.",
+            Player::White,
+        )
+        .unwrap();
+        // a1 and b1 for White; a2 for Black. c1 covers White's own piece.
+        assert_eq!(state.gobble_count(Player::White), 2);
+        assert_eq!(state.gobble_count(Player::Black), 1);
+        assert_eq!(GameState::new().gobble_count(Player::White), 0);
+    }
+
+    #[test]
+    fn a_constant_evaluator_scores_every_leaf() {
+        use std::sync::{
+            atomic::{AtomicU64, Ordering as AtomicOrdering},
+            Arc,
+        };
+
+        struct Constant(Arc<AtomicU64>);
+
+        impl Evaluator for Constant {
+            fn evaluate(&self, _: &GameState) -> Score {
+                self.0.fetch_add(1, AtomicOrdering::Relaxed);
+                Score::Balanced(42)
+            }
+        }
+
+        let calls = Arc::new(AtomicU64::new(0));
+        let state = play(&["3@b2"]);
+        let mut engine = MinimaxEngine::new(2).with_evaluator(Constant(Arc::clone(&calls)));
+        let result = engine.search(&state).unwrap();
+        assert_eq!(result.score, Score::Balanced(42));
+        assert!(calls.load(AtomicOrdering::Relaxed) > 0);
+        assert!(state.legal_moves().contains(&result.best_move.unwrap()));
+    }
+
+    #[test]
+    fn batched_and_single_evaluation_agree() {
+        let weights = EvalWeights::default();
+        let mut rng = StdRng::seed_from_u64(41);
+        for target_ply in [0, 6, 12] {
+            let state = GameState::random_position(&mut rng, target_ply);
+            let children: Vec<GameState> =
+                state.branch().into_iter().map(|(_, child)| child).collect();
+            let single: Vec<Score> = children
+                .iter()
+                .map(|child| weights.evaluate(child))
+                .collect();
+            assert_eq!(weights.evaluate_batch(&children), single);
+
+            let expected = alpha_beta(&mut state.clone(), 2, Score::MIN, Score::MAX, None);
+            for batch_size in [1, 7, 1000] {
+                assert_eq!(minimax_batched(&state, 2, &weights, batch_size), expected);
+            }
+        }
+
+        // White completes row 1 next move, so the win is backed up from a leaf.
+        let winning =
+            GameState::from_position("W3,W3,W3,./B2,B2,.,./.,.,.,./.,.,.,.", Player::White)
+                .unwrap();
+        let expected = alpha_beta(&mut winning.clone(), 2, Score::MIN, Score::MAX, None);
+        assert_eq!(expected, Score::WhiteFavored(1));
+        assert_eq!(minimax_batched(&winning, 2, &weights, 7), expected);
+    }
+
+    #[test]
+    fn searching_a_finished_game_expands_nothing() {
+        let won = GameState::from_position("W2,W2,W3,W3/B2,B2,B2,./.,.,.,./.,.,.,.", Player::Black)
+            .unwrap();
+        let result = MinimaxEngine::new(3).search(&won).unwrap();
+        assert_eq!(result.best_move, None);
+        assert_eq!(result.score, Score::for_player(Player::White));
+        assert!(result.pv.is_empty());
+        assert_eq!(result.stats.nodes, 0);
+    }
+
+    #[test]
+    fn dead_draws_are_not_over_and_still_get_a_move() {
+        // With three pieces each, neither side has enough pieces for a line.
+        let mut state = GameState::with_reserves([3, 0, 0, 0], [3, 0, 0, 0]);
+        for game_move in moves(&["0@a1", "0@a3", "0@b1", "0@b3", "0@c1", "0@c3"]) {
+            state.make_move(game_move);
+        }
+        assert!(state.is_dead_draw());
+        assert_eq!(state.result(), None);
+        let game_move = MinimaxEngine::new(2).choose_move(&state).unwrap();
+        assert_eq!(state.check_move(game_move), Ok(()));
+    }
+
+    #[test]
+    fn symmetric_opening_moves_rank_equally() {
+        let start = GameState::new();
+        let ranked = start.analyze(2);
+        let score_of = |game_move: GameMove| {
+            ranked
+                .iter()
+                .find(|&&(ranked_move, _)| ranked_move == game_move)
+                .map(|&(_, score)| score)
+                .unwrap()
+        };
+        for &(game_move, score) in &ranked {
+            let equivalents = start.symmetric_equivalents(game_move);
+            assert!(!equivalents.is_empty(), "{game_move}");
+            for equivalent in equivalents {
+                assert_eq!(score_of(equivalent), score, "{game_move} and {equivalent}");
+            }
+        }
+        assert_eq!(start.analyze_symmetric(2), ranked);
+    }
+
+    #[test]
+    fn snapshots_of_a_tree_are_unaffected_by_deeper_search() {
+        let count = |node: &Node| {
+            let mut nodes = 0;
+            node.walk(&mut |_, _, _| nodes += 1);
+            nodes
+        };
+        let mut node = Node::new(play(&["3@b2", "1@c3"]));
+        node.branch(1);
+        let snapshot = node.clone();
+        let (snapshot_nodes, snapshot_score) = (count(&snapshot), snapshot.score);
+        // The clone shares its children with the original until one of them changes.
+        let first_child = |node: &Node| match node.state {
+            NodeState::Branches(ref branches) => Arc::clone(&branches[0].1),
+            _ => panic!("the node was expanded"),
+        };
+        assert!(Arc::ptr_eq(&first_child(&node), &first_child(&snapshot)));
+
+        node.branch(2);
+        assert!(!Arc::ptr_eq(&first_child(&node), &first_child(&snapshot)));
+        assert!(count(&node) > snapshot_nodes);
+        assert_eq!(count(&snapshot), snapshot_nodes);
+        assert_eq!(snapshot.score, snapshot_score);
+        let NodeState::Branches(ref branches) = snapshot.state else {
+            panic!("the snapshot was expanded");
+        };
+        assert!(branches
+            .iter()
+            .all(|(_, child)| matches!(child.state, NodeState::GameState(_))));
+    }
+
+    #[test]
+    fn placements_a_free_relocation_reaches_are_dropped_on_request() {
+        let options = MoveOptions {
+            prefer_relocation: true,
+            ..MoveOptions::default()
+        };
+        let placement: GameMove = "0@c2".parse().unwrap();
+
+        // Lifting a1 uncovers nothing, so moving it to c2 saves a reserve piece.
+        let free =
+            GameState::from_position("W3,.,.,./.,.,.,./.,.,.,./.,.,.,B3", Player::White).unwrap();
+        assert!(free.legal_moves().contains(&placement));
+        let filtered = free.legal_moves_with(&options);
+        assert!(!filtered.contains(&placement));
+        assert!(filtered.contains(&"a1-c2".parse().unwrap()));
+
+        // Lifting a1 would uncover Black's piece, so the placement stays.
+        let covering =
+            GameState::from_position("B0|W3,.,.,./.,.,.,./.,.,.,./.,.,.,B3", Player::White)
+                .unwrap();
+        assert!(covering.legal_moves_with(&options).contains(&placement));
+    }
+
+    /// Checks every position the search scores.
+    struct ConsistencyCheck;
+
+    impl Evaluator for ConsistencyCheck {
+        fn evaluate(&self, state: &GameState) -> Score {
+            state.assert_consistent();
+            state.raw_score()
+        }
+    }
+
+    #[test]
+    fn deep_searches_never_over_place_a_size() {
+        // With three of the smallest size each, both reserves run out two plies from
+        // here and the search goes on moving pieces around.
+        let mut state = GameState::with_reserves([3, 0, 0, 0], [3, 0, 0, 0]);
+        for game_move in moves(&["0@a1", "0@d4", "0@b2", "0@c3"]) {
+            state.make_move(game_move);
+        }
+        let mut engine = MinimaxEngine::new(6)
+            .with_transposition_table(1 << 16, ReplacementPolicy::default())
+            .with_evaluator(ConsistencyCheck);
+        let result = engine.search_root(&state, 6).unwrap();
+        assert!(result.best_move.is_some());
+    }
+
+    #[test]
+    fn random_lines_keep_the_reserves_consistent() {
+        let mut rng = StdRng::seed_from_u64(0);
+        GameState::new().check_random_lines(&mut rng, 200, 40);
+    }
+
+    #[test]
+    fn eval_units_rise_with_the_score_and_clamp_at_the_ends() {
+        let scale = 100;
+        let ordered = [
+            Score::BlackFavored(0),
+            Score::BlackFavored(5),
+            Score::Balanced(-500),
+            Score::Balanced(-40),
+            Score::Balanced(0),
+            Score::Balanced(10),
+            Score::Balanced(150),
+            Score::WhiteFavored(5),
+            Score::WhiteFavored(0),
+        ];
+        for pair in ordered.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert!(pair[0].to_eval_units(scale) <= pair[1].to_eval_units(scale));
+        }
+        assert!(Score::Balanced(10).to_eval_units(scale) > Score::Balanced(0).to_eval_units(scale));
+        assert_eq!(Score::Balanced(50).to_eval_units(scale), 0.5);
+        assert_eq!(Score::Balanced(150).to_eval_units(scale), 1.0);
+        assert_eq!(Score::Balanced(-500).to_eval_units(scale), -1.0);
+        assert_eq!(Score::WhiteFavored(5).to_eval_units(scale), 1.0);
+        assert_eq!(Score::BlackFavored(5).to_eval_units(scale), -1.0);
+    }
+
+    #[test]
+    fn practice_grades_a_missed_block_as_a_blunder_and_the_top_move_as_best() {
+        // White has three in the a file; Black must cover a4.
+        let state = play(&["3@a1", "0@d4", "3@a2", "0@d3", "3@a3"]);
+        let best = state.analyze(2)[0].0;
+        assert_eq!(best.dest(), Some((3, 0)));
+        assert_eq!(state.grade_move(&best, 2), Ok(MoveGrade::Best));
+        let missed_block = moves(&["0@c4"])[0];
+        assert_eq!(state.grade_move(&missed_block, 2), Ok(MoveGrade::Blunder));
+    }
+
+    #[test]
+    fn the_incremental_hash_never_diverges_in_long_random_games() {
+        let mut rng = StdRng::seed_from_u64(43);
+        let mut state = GameState::new();
+        let mut line = Vec::new();
+        for _ in 0..2000 {
+            if state.result().is_some() || line.len() >= 200 {
+                while let Some(game_move) = line.pop() {
+                    state.unmake_move(game_move);
+                    assert_eq!(state.zobrist(), state.full_zobrist());
+                }
+                assert_eq!(state.zobrist(), GameState::new().zobrist());
+            }
+            let game_move = *state.legal_moves().iter().choose(&mut rng).unwrap();
+            state.make_move(game_move);
+            line.push(game_move);
+            assert_eq!(state.zobrist(), state.full_zobrist());
+        }
+    }
+
+    #[test]
+    fn capped_move_lists_say_whether_moves_were_left_out() {
+        let state = GameState::new();
+        let all: Vec<GameMove> = state.ordered_moves(&CoverFirst).collect();
+        let (capped, truncated) = state.legal_moves_capped(5);
+        assert_eq!(capped.len(), 5);
+        assert!(truncated);
+        assert_eq!(capped, all[..5]);
+        assert_eq!(state.legal_moves_capped(all.len()), (all.clone(), false));
+        assert_eq!(state.legal_moves_capped(all.len() + 1), (all, false));
+    }
+
+    #[test]
+    fn passing_flips_the_turn() {
+        let rules = RuleSet {
+            allow_pass: true,
+            ..RuleSet::default()
+        };
+        let mut state = play_with(rules, &["3@a1"]);
+        assert!(state.legal_moves().contains(&GameMove::Pass));
+        state.make_move(GameMove::Pass);
+        assert_eq!(state.turn(), Player::White);
+        assert!(!state.legal_moves().contains(&GameMove::Pass));
+    }
+
+    #[test]
+    fn zobrist_tells_a_pass_apart() {
+        let rules = RuleSet {
+            allow_pass: true,
+            ..RuleSet::default()
+        };
+        let passed = play_with(rules, &["3@a1", "3@d4", "--"]);
+        let placed = play_with(rules, &["--", "3@d4", "3@a1"]);
+        assert_eq!(passed.position_string(), placed.position_string());
+        assert_eq!(passed.turn(), placed.turn());
+        assert_ne!(passed.zobrist(), placed.zobrist());
+        assert_ne!(passed.legal_moves(), placed.legal_moves());
+    }
+
+    #[test]
+    fn zobrist_tells_apart_relocations_that_may_not_be_reversed() {
+        let lines = [
+            ["3@a1", "3@d4", "a1-b1", "d4-c4"],
+            ["3@c1", "3@d4", "c1-b1", "d4-c4"],
+        ];
+        let forbid = RuleSet {
+            forbid_reversal: true,
+            ..RuleSet::default()
+        };
+        let [from_a1, from_c1] = lines.map(|line| play_with(forbid, &line));
+        assert_eq!(from_a1.position_string(), from_c1.position_string());
+        assert_ne!(from_a1.zobrist(), from_c1.zobrist());
+        assert_ne!(from_a1.legal_moves(), from_c1.legal_moves());
+
+        let [from_a1, from_c1] = lines.map(|line| play_with(RuleSet::default(), &line));
+        assert_eq!(from_a1.zobrist(), from_c1.zobrist());
+    }
+
+    #[test]
+    fn empty_stacks_have_full_capacity_and_full_stacks_none() {
+        assert_eq!(Stack::empty().capacity_remaining(), NUM_SIZES);
+        let full = Stack {
+            pieces: [Color::White, Color::Black, Color::White, Color::Black],
+        };
+        assert_eq!(full.capacity_remaining(), 0);
+        let state = GameState::new();
+        assert_eq!(state.board_capacity(), NUM_SIZES * BOARD_ROWS * BOARD_COLS);
+        let state = play(&["3@a1", "0@d4"]);
+        assert_eq!(state.board.contents[0][0].capacity_remaining(), 0);
+        assert_eq!(
+            state.board.contents[3][3].capacity_remaining(),
+            NUM_SIZES - 1
+        );
+        assert_eq!(
+            state.board_capacity(),
+            NUM_SIZES * BOARD_ROWS * BOARD_COLS - 5
+        );
+    }
+
+    #[test]
+    fn masked_out_sizes_are_never_placed() {
+        let rules = RuleSet {
+            usable_sizes: [false, false, true, true],
+            ..RuleSet::default()
+        };
+        let mut rng = StdRng::seed_from_u64(47);
+        for _ in 0..50 {
+            let mut state = GameState::new().with_rules(rules);
+            for _ in 0..40 {
+                let legal = state.legal_moves();
+                if state.result().is_some() || legal.is_empty() {
+                    break;
+                }
+                for game_move in &legal {
+                    if let GameMove::Place { size, .. } = *game_move {
+                        assert!(size >= 2, "placed size {size}");
+                    }
+                }
+                state.make_move(*legal.iter().choose(&mut rng).unwrap());
+            }
+        }
+        // A small piece already on the board moves only while the rules allow it.
+        let small_on_board =
+            GameState::from_position("W0,.,.,./.,.,.,./.,.,.,./.,.,.,.", Player::White).unwrap();
+        let relocates_small = |state: GameState| {
+            state
+                .legal_moves()
+                .iter()
+                .any(|game_move| game_move.source() == Some((0, 0)))
+        };
+        assert!(relocates_small(small_on_board.clone().with_rules(rules)));
+        let pinned = RuleSet {
+            move_unusable_sizes: false,
+            ..rules
+        };
+        assert!(!relocates_small(small_on_board.with_rules(pinned)));
+    }
+
+    #[test]
+    fn rebuilding_a_node_hits_the_score_cache() {
+        let mut cache = ScoreCache::new(2);
+        let state = play(&["3@a1", "2@b2"]);
+        let first = Node::new_cached(state.clone(), &mut cache);
+        assert_eq!((cache.hits(), cache.misses()), (0, 1));
+        let second = Node::new_cached(state.clone(), &mut cache);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(first.score, state.raw_score());
+        assert_eq!(second.score, first.score);
+        // Two newer positions push the least recently used one out.
+        Node::new_cached(play(&["3@a1"]), &mut cache);
+        Node::new_cached(GameState::new(), &mut cache);
+        Node::new_cached(state, &mut cache);
+        assert_eq!((cache.hits(), cache.misses()), (1, 4));
+    }
+
+    #[test]
+    fn invalid_configs_fail_the_search_cleanly() {
+        let state = GameState::new();
+        let no_depth = EngineConfig::default().with_depth(0);
+        assert_eq!(no_depth.validate(), Err(EngineError::InvalidDepth(0)));
+        assert_eq!(
+            MinimaxEngine::from_config(no_depth)
+                .search(&state)
+                .unwrap_err(),
+            EngineError::InvalidDepth(0)
+        );
+        assert_eq!(
+            MinimaxEngine::new(2).search_root(&state, -1).unwrap_err(),
+            EngineError::InvalidDepth(-1)
+        );
+        let no_slots =
+            EngineConfig::default().with_transposition_table(0, ReplacementPolicy::default());
+        assert_eq!(
+            MinimaxEngine::from_config(no_slots)
+                .search(&state)
+                .unwrap_err(),
+            EngineError::EmptyTranspositionTable
+        );
+        assert!(MinimaxEngine::new(1).search(&state).is_ok());
+    }
+
+    #[test]
+    fn a_depth_one_search_always_takes_an_immediate_win() {
+        let wins_now = |state: &GameState, game_move: GameMove| {
+            let mut after = state.clone();
+            after.make_move(game_move);
+            after.result() == Some(GameResult::Win(state.turn))
+        };
+        let mut rng = StdRng::seed_from_u64(53);
+        let mut checked = 0;
+        while checked < 20 {
+            let target_ply = rng.gen_range(5..20);
+            let state = GameState::random_position(&mut rng, target_ply);
+            if state.result().is_some()
+                || !state
+                    .legal_moves()
+                    .into_iter()
+                    .any(|game_move| wins_now(&state, game_move))
+            {
+                continue;
+            }
+            let chosen = MinimaxEngine::new(1).choose_move(&state).unwrap();
+            assert!(
+                wins_now(&state, chosen),
+                "missed a win in {}",
+                state.position_string()
+            );
+            checked += 1;
+        }
+    }
+
+    #[test]
+    fn rollouts_from_a_won_position_return_the_winner_at_once() {
+        let mut won = play(&["3@a1", "0@d4", "3@a2", "0@d3", "3@a3", "0@c4", "2@a4"]);
+        let before = won.zobrist();
+        let mut rng = StdRng::seed_from_u64(59);
+        assert_eq!(won.rollout(&mut rng, 100), GameResult::Win(Player::White));
+        assert_eq!(won.zobrist(), before);
+
+        let mut first = GameState::new();
+        let mut second = GameState::new();
+        let result = first.rollout(&mut StdRng::seed_from_u64(61), 60);
+        assert_eq!(second.rollout(&mut StdRng::seed_from_u64(61), 60), result);
+        assert_eq!(first.zobrist(), second.zobrist());
+        first.assert_consistent();
+    }
+
+    #[test]
+    fn sorting_shuffled_moves_gives_one_canonical_order() {
+        use rand::seq::SliceRandom;
+
+        let state = play(&["3@a1", "0@b2", "2@c3"]);
+        let mut canonical = state.legal_moves();
+        canonical.push(GameMove::Pass);
+        canonical.sort();
+        let mut rng = StdRng::seed_from_u64(67);
+        for _ in 0..10 {
+            let mut shuffled = canonical.clone();
+            shuffled.shuffle(&mut rng);
+            shuffled.sort();
+            assert_eq!(shuffled, canonical);
+        }
+        for pair in canonical.windows(2) {
+            assert_eq!(pair[0].cmp(&pair[1]), Ordering::Less);
+        }
+        let first_relocation = canonical
+            .iter()
+            .position(|game_move| matches!(game_move, GameMove::Move { .. }))
+            .unwrap();
+        assert!(canonical[..first_relocation]
+            .iter()
+            .all(|game_move| matches!(game_move, GameMove::Place { .. })));
+        assert_eq!(canonical.last(), Some(&GameMove::Pass));
+        assert_eq!(
+            moves(&["0@d4", "1@a1"]).into_iter().min(),
+            moves(&["0@d4"]).into_iter().next()
+        );
+    }
+
+    #[test]
+    fn root_symmetry_searches_fewer_root_moves() {
+        let searched_at_depth_one = |state: &GameState, root_symmetry: bool| {
+            let config = EngineConfig::default().with_root_symmetry(root_symmetry);
+            let result = MinimaxEngine::from_config(config)
+                .search_root(state, 1)
+                .unwrap();
+            (result.stats.nodes - 1, result.best_move.unwrap())
+        };
+        let start = GameState::new();
+        let (all, _) = searched_at_depth_one(&start, false);
+        let (distinct, _) = searched_at_depth_one(&start, true);
+        assert_eq!(all, start.legal_moves().len() as u64);
+        assert_eq!(distinct, start.distinct_moves().len() as u64);
+        assert!(distinct < all);
+
+        let mut rng = StdRng::seed_from_u64(71);
+        for target_ply in [1, 3, 6] {
+            let state = GameState::random_position(&mut rng, target_ply);
+            let (_, best_move) = searched_at_depth_one(&state, true);
+            assert_eq!(state.check_move(best_move), Ok(()));
+        }
+    }
+
+    #[test]
+    fn a_fresh_game_with_black_to_move_places_black_pieces() {
+        let mut state = GameState::new_with_turn(Player::Black);
+        assert_eq!(state.turn, Player::Black);
+        assert_eq!(state.legal_moves(), GameState::new().legal_moves());
+        assert_ne!(state.zobrist(), GameState::new().zobrist());
+        state.assert_consistent();
+        let game_move = MinimaxEngine::new(2).choose_move(&state).unwrap();
+        let dest = game_move.dest().unwrap();
+        let size = game_move.size(&state.board).unwrap();
+        state.make_move(game_move);
+        assert_eq!(state.top_piece(dest), Some((Color::Black, size)));
+        assert_eq!(state.reserves(Player::Black)[size], NUM_EACH_SIZE - 1);
+        assert_eq!(state.reserves(Player::White), [NUM_EACH_SIZE; NUM_SIZES]);
+        assert_eq!(state.turn, Player::White);
+        state.assert_consistent();
+    }
+
+    #[test]
+    fn maintained_top_colors_match_a_recompute_in_random_games() {
+        let mut rng = StdRng::seed_from_u64(73);
+        for _ in 0..30 {
+            let mut state = GameState::new();
+            let mut line = Vec::new();
+            while state.result().is_none() && line.len() < 60 {
+                let game_move = *state.legal_moves().iter().choose(&mut rng).unwrap();
+                state.make_move(game_move);
+                line.push(game_move);
+                assert_eq!(state.top_colors(), state.full_top_colors());
+            }
+            while let Some(game_move) = line.pop() {
+                state.unmake_move(game_move);
+                assert_eq!(state.top_colors(), state.full_top_colors());
+            }
+        }
+    }
+
+    #[test]
+    fn no_move_builds_past_the_stack_height_cap() {
+        let rules = RuleSet {
+            max_stack_height: 2,
+            ..RuleSet::default()
+        };
+        let state = GameState::from_position("W0|B1,W0,.,./.,.,.,./.,.,.,./.,.,.,.", Player::Black)
+            .unwrap()
+            .with_rules(rules);
+        let legal = state.legal_moves();
+        assert!(!legal
+            .iter()
+            .any(|game_move| game_move.dest() == Some((0, 0))));
+        assert!(legal.contains(&moves(&["1@b1"])[0]));
+        assert!(legal
+            .iter()
+            .any(|game_move| game_move.source() == Some((0, 0))));
+
+        let mut rng = StdRng::seed_from_u64(79);
+        for _ in 0..30 {
+            let mut state = GameState::new().with_rules(rules);
+            for _ in 0..40 {
+                let legal = state.legal_moves();
+                if state.result().is_some() || legal.is_empty() {
+                    break;
+                }
+                state.make_move(*legal.iter().choose(&mut rng).unwrap());
+                let tallest = state
+                    .board
+                    .contents
+                    .iter()
+                    .flatten()
+                    .map(Stack::height)
+                    .max();
+                assert!(tallest <= Some(2));
+            }
+        }
+    }
+
+    #[test]
+    fn streaming_search_reports_each_completed_depth() {
+        let state = play(&["3@a1", "3@d4", "2@b2", "2@c3"]);
+        let mut iterations = Vec::new();
+        let mut node = Node::new(state.clone());
+        node.search_streaming(3, |info| iterations.push(info));
+        let depths: Vec<i32> = iterations.iter().map(|info| info.depth).collect();
+        assert_eq!(depths, [1, 2, 3]);
+        for info in &iterations {
+            assert_eq!(info.pv.first().copied(), info.best_move);
+            assert!(info.nodes > 1);
+        }
+        let mut fresh = Node::new(state);
+        fresh.branch(3);
+        let last = iterations.last().unwrap();
+        assert_eq!((last.best_move, last.score), (fresh.best_move, fresh.score));
+
+        // A forced win ends the search at the depth that finds it.
+        let mut iterations = Vec::new();
+        let mut node = Node::new(play(&["3@a1", "0@d4", "3@a2", "0@d3", "3@a3", "0@c4"]));
+        node.search_streaming(4, |info| iterations.push(info));
+        assert_eq!(iterations.len(), 1);
+        assert!(iterations[0].score.is_decisive());
+    }
+
+    #[test]
+    fn positions_reached_differently_are_position_eq_but_not_equal() {
+        let played = play(&["3@a1", "3@d4", "2@b2", "2@c3"]);
+        let transposed = play(&["2@b2", "2@c3", "3@a1", "3@d4"]);
+        let set_up = GameState::from_position(&played.position_string(), played.turn).unwrap();
+        for other in [&transposed, &set_up] {
+            assert!(played.position_eq(other));
+            assert!(other.position_eq(&played));
+            assert!(played != *other);
+            assert_eq!(played.zobrist(), other.zobrist());
+        }
+        assert!(!played.position_eq(&play(&["3@a1", "3@d4", "2@b2", "2@c2"])));
+
+        // The same board, but only one side may pass again.
+        let rules = RuleSet {
+            allow_pass: true,
+            ..RuleSet::default()
+        };
+        let passed = play_with(rules, &["3@a1", "3@d4", "--"]);
+        let placed = play_with(rules, &["--", "3@d4", "3@a1"]);
+        assert!(!passed.position_eq(&placed));
+    }
+
+    #[test]
+    fn three_in_a_row_wins_when_the_win_length_is_three() {
+        let three = RuleSet {
+            win_length: 3,
+            ..RuleSet::default()
+        };
+        for line in [
+            ["3@a1", "3@a4", "3@b1", "3@b4", "2@c1"],
+            ["3@a1", "3@d1", "3@a2", "3@d2", "2@a3"],
+            ["3@a1", "3@d1", "3@b2", "3@d2", "2@c3"],
+            ["3@d2", "3@a1", "3@c3", "3@a2", "2@b4"],
+        ] {
+            assert_eq!(
+                play_with(three, &line).winner(),
+                Some(Player::White),
+                "{line:?}"
+            );
+            assert_eq!(play(&line).winner(), None, "{line:?}");
+        }
+        let short = play_with(three, &["3@a1", "3@a4", "3@b1"]);
+        assert_eq!(short.winner(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "win length")]
+    fn a_zero_win_length_is_rejected() {
+        let rules = RuleSet {
+            win_length: 0,
+            ..RuleSet::default()
+        };
+        GameState::new().with_rules(rules);
+    }
+
+    #[test]
+    #[should_panic(expected = "win length")]
+    fn a_win_length_longer_than_the_board_is_rejected() {
+        let rules = RuleSet {
+            win_length: BOARD_ROWS.max(BOARD_COLS) + 1,
+            ..RuleSet::default()
+        };
+        GameState::new().with_rules(rules);
+    }
+
+    #[test]
+    fn previews_score_the_position_each_move_leads_to() {
+        let mut rng = StdRng::seed_from_u64(83);
+        for target_ply in [0, 4, 8, 12] {
+            let state = GameState::random_position(&mut rng, target_ply);
+            let previews: Vec<(GameMove, Score)> = state.moves_with_preview().collect();
+            assert_eq!(
+                previews
+                    .iter()
+                    .map(|&(game_move, _)| game_move)
+                    .collect::<Vec<_>>(),
+                state.legal_moves()
+            );
+            for (game_move, score) in previews {
+                let mut after = state.clone();
+                after.apply_move(game_move);
+                assert_eq!(score, after.raw_score());
+            }
+        }
+    }
+
+    #[test]
+    fn a_tiny_memory_ceiling_still_gives_a_legal_move_within_budget() {
+        // Room for a few plies once the table takes its half.
+        let budget = MinimaxEngine::new(6).estimated_memory_bytes(8);
+        let config = EngineConfig::default()
+            .with_depth(6)
+            .with_transposition_table(1 << 20, ReplacementPolicy::default())
+            .with_max_memory_bytes(budget);
+        let mut engine = MinimaxEngine::from_config(config);
+        let table_bytes = engine
+            .transposition_table
+            .as_ref()
+            .map_or(0, TranspositionTable::memory_bytes);
+        assert!(table_bytes <= budget / 2);
+        let state = play(&["3@a1", "3@d4"]);
+        let result = engine.search(&state).unwrap();
+        assert_eq!(state.check_move(result.best_move.unwrap()), Ok(()));
+        assert!((2..6).contains(&result.depth_reached));
+        assert!(engine.estimated_memory_bytes(result.depth_reached as i32) <= budget);
+    }
+
+    #[test]
+    fn size_buckets_together_hold_every_legal_move() {
+        let mut rng = StdRng::seed_from_u64(89);
+        for target_ply in [0, 5, 10, 15, 20] {
+            let state = GameState::random_position(&mut rng, target_ply);
+            let by_size = state.moves_by_size();
+            for (size, bucket) in by_size.iter().enumerate() {
+                for game_move in bucket {
+                    assert_eq!(game_move.size(&state.board), Some(size));
+                }
+            }
+            let mut joined: Vec<GameMove> = by_size.concat();
+            let mut legal = state.legal_moves();
+            joined.sort();
+            legal.sort();
+            assert_eq!(joined, legal);
+        }
+    }
+
+    #[test]
+    fn threatened_lines_list_each_line_one_playable_gap_from_done() {
+        let state =
+            GameState::from_position("W3,W0,W0,./W1,.,.,./W1,.,.,./.,.,.,B3", Player::Black)
+                .unwrap();
+        let mut lines = state.threatened_lines(Color::White);
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                vec![(0, 0), (0, 1), (0, 2), (0, 3)],
+                vec![(0, 0), (1, 0), (2, 0), (3, 0)],
+            ]
+        );
+        assert!(state.threatened_lines(Color::Black).is_empty());
+
+        // A gap under the largest size can't be filled, so row 1 is no threat.
+        let blocked =
+            GameState::from_position("W3,W0,W0,B3/W1,.,.,./W1,.,.,./.,.,.,.", Player::Black)
+                .unwrap();
+        assert_eq!(
+            blocked.threatened_lines(Color::White),
+            [vec![(0, 0), (1, 0), (2, 0), (3, 0)]]
+        );
+    }
+
+    #[test]
+    fn only_gobbles_are_generated_when_captures_are_mandatory() {
+        let rules = RuleSet {
+            mandatory_capture: true,
+            ..RuleSet::default()
+        };
+        assert_eq!(
+            GameState::new().with_rules(rules).legal_moves(),
+            GameState::new().legal_moves()
+        );
+        let state = play_with(rules, &["0@a1"]);
+        assert_eq!(state.legal_moves(), moves(&["1@a1", "2@a1", "3@a1"]));
+        assert!(play(&["0@a1"]).legal_moves().len() > 3);
+        assert!(!state.is_legal(&moves(&["3@d4"])[0]));
+        let reply = MinimaxEngine::new(2).choose_move(&state).unwrap();
+        assert!(state.captures(reply));
+    }
+}
@@ -0,0 +1,268 @@
+//! A small, hashable snapshot of a position, for use as a map key.
+
+use std::{
+    array,
+    error::Error,
+    fmt::{self, Display},
+    num::TryFromIntError,
+};
+
+use crate::{Color, GameState, Player, BOARD_COLS, BOARD_ROWS, NUM_SIZES};
+
+const CELL_BITS: usize = NUM_SIZES * 2;
+const _: () = assert!(
+    BOARD_ROWS * BOARD_COLS * CELL_BITS <= 128,
+    "the board doesn't fit in a PositionKey"
+);
+
+/// The board, reserves and side to move packed into a few machine words. Equal
+/// positions give equal keys; the rules and move history are not part of the key.
+///
+/// Each piece takes two bits (empty, White or Black), packed by row, column and
+/// size. Conversion fails if a reserve count doesn't fit in a byte.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PositionKey {
+    board: u128,
+    /// White's reserves by size, then Black's.
+    reserves: [u8; 2 * NUM_SIZES],
+    black_to_move: bool,
+}
+
+/// The tag that starts every key encoded by `PositionKey::to_bytes`.
+pub const MAGIC: [u8; 4] = *b"GBPK";
+/// The format version `PositionKey::to_bytes` writes. Bump it whenever the layout
+/// changes, including when the board size or number of piece sizes does.
+pub const FORMAT_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = MAGIC.len() + 1;
+const V1_BODY_LEN: usize = 16 + 2 * NUM_SIZES + 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The data doesn't start with `MAGIC`.
+    BadMagic,
+    /// Written by a version of the format this build doesn't know.
+    UnsupportedVersion(u8),
+    /// The data is the wrong length for its version.
+    Length { expected: usize, found: usize },
+    /// The side-to-move byte is neither 0 nor 1.
+    InvalidTurn(u8),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::BadMagic => write!(f, "not an encoded position key"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported position key format version {version}")
+            }
+            DecodeError::Length { expected, found } => {
+                write!(f, "expected {expected} bytes but found {found}")
+            }
+            DecodeError::InvalidTurn(byte) => write!(f, "invalid side to move {byte}"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+impl PositionKey {
+    /// Encode the key as `MAGIC`, `FORMAT_VERSION`, then the board as a
+    /// little-endian `u128`, the reserve counts and a side-to-move byte.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + V1_BODY_LEN);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.extend_from_slice(&self.board.to_le_bytes());
+        bytes.extend_from_slice(&self.reserves);
+        bytes.push(u8::from(self.black_to_move));
+        bytes
+    }
+
+    /// Decode a key written by `to_bytes`, in this or any earlier supported version.
+    pub fn from_bytes(bytes: &[u8]) -> Result<PositionKey, DecodeError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(DecodeError::Length {
+                expected: HEADER_LEN,
+                found: bytes.len(),
+            });
+        }
+        let (header, body) = bytes.split_at(HEADER_LEN);
+        if header[..MAGIC.len()] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        // When the layout changes, keep the old decoders and have each one convert
+        // its layout to the current `PositionKey`.
+        match header[MAGIC.len()] {
+            1 => decode_v1(body),
+            version => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+fn decode_v1(body: &[u8]) -> Result<PositionKey, DecodeError> {
+    if body.len() != V1_BODY_LEN {
+        return Err(DecodeError::Length {
+            expected: HEADER_LEN + V1_BODY_LEN,
+            found: HEADER_LEN + body.len(),
+        });
+    }
+    let (board, rest) = body.split_at(16);
+    let (reserves, turn) = rest.split_at(2 * NUM_SIZES);
+    let black_to_move = match turn[0] {
+        0 => false,
+        1 => true,
+        byte => return Err(DecodeError::InvalidTurn(byte)),
+    };
+    Ok(PositionKey {
+        board: u128::from_le_bytes(board.try_into().unwrap()),
+        reserves: reserves.try_into().unwrap(),
+        black_to_move,
+    })
+}
+
+fn color_bits(color: Color) -> u128 {
+    match color {
+        Color::Empty => 0,
+        Color::White => 1,
+        Color::Black => 2,
+    }
+}
+
+fn bits_color(bits: u128) -> Color {
+    match bits {
+        1 => Color::White,
+        2 => Color::Black,
+        _ => Color::Empty,
+    }
+}
+
+impl TryFrom<&GameState> for PositionKey {
+    type Error = TryFromIntError;
+
+    fn try_from(state: &GameState) -> Result<Self, Self::Error> {
+        let mut board = 0;
+        for (row, stacks) in state.board.contents.iter().enumerate() {
+            for (col, stack) in stacks.iter().enumerate() {
+                for (size, &color) in stack.pieces.iter().enumerate() {
+                    let shift = (row * BOARD_COLS + col) * CELL_BITS + size * 2;
+                    board |= color_bits(color) << shift;
+                }
+            }
+        }
+        let mut reserves = [0; 2 * NUM_SIZES];
+        for (slot, &count) in reserves
+            .iter_mut()
+            .zip(state.white_pieces.iter().chain(&state.black_pieces))
+        {
+            *slot = u8::try_from(count)?;
+        }
+        Ok(PositionKey {
+            board,
+            reserves,
+            black_to_move: state.turn == Player::Black,
+        })
+    }
+}
+
+/// The position the key was taken from, under the standard rules and with no history.
+impl From<PositionKey> for GameState {
+    fn from(key: PositionKey) -> Self {
+        let (white, black) = key.reserves.split_at(NUM_SIZES);
+        let mut state = GameState::with_reserves(
+            array::from_fn(|size| i32::from(white[size])),
+            array::from_fn(|size| i32::from(black[size])),
+        );
+        for (row, stacks) in state.board.contents.iter_mut().enumerate() {
+            for (col, stack) in stacks.iter_mut().enumerate() {
+                for (size, color) in stack.pieces.iter_mut().enumerate() {
+                    let shift = (row * BOARD_COLS + col) * CELL_BITS + size * 2;
+                    *color = bits_color((key.board >> shift) & 0b11);
+                }
+            }
+        }
+        if key.black_to_move {
+            state.turn = Player::Black;
+        }
+        state.resync();
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameMove;
+
+    fn play(notations: &[&str]) -> GameState {
+        let mut state = GameState::new();
+        for notation in notations {
+            let game_move: GameMove = notation.parse().unwrap();
+            state.make_move(game_move);
+        }
+        state
+    }
+
+    fn key(state: &GameState) -> PositionKey {
+        PositionKey::try_from(state).unwrap()
+    }
+
+    #[test]
+    fn equal_positions_give_equal_keys() {
+        let first = play(&["3@a1", "0@d4", "2@b2"]);
+        let transposed = play(&["2@b2", "0@d4", "3@a1"]);
+        assert_eq!(key(&first), key(&transposed));
+        assert_ne!(key(&first), key(&play(&["3@a1", "0@d4"])));
+        assert_ne!(key(&first), key(&play(&["3@a1", "0@d4", "2@b3"])));
+    }
+
+    #[test]
+    fn keys_round_trip_to_the_same_position() {
+        for state in [
+            GameState::new(),
+            play(&["3@a1", "0@b2", "2@b2", "3@d4", "a1-c1"]),
+        ] {
+            let restored = GameState::from(key(&state));
+            assert_eq!(restored.position_string(), state.position_string());
+            assert_eq!(restored.turn(), state.turn());
+            for player in [Player::White, Player::Black] {
+                assert_eq!(restored.reserves(player), state.reserves(player));
+            }
+            assert_eq!(key(&restored), key(&state));
+        }
+    }
+
+    #[test]
+    fn version_one_blobs_decode() {
+        let state = play(&["3@a1", "0@b2", "2@b2"]);
+        let bytes = key(&state).to_bytes();
+        assert_eq!(bytes[..MAGIC.len()], MAGIC);
+        assert_eq!(bytes[MAGIC.len()], 1);
+        assert_eq!(bytes.len(), HEADER_LEN + V1_BODY_LEN);
+        assert_eq!(PositionKey::from_bytes(&bytes), Ok(key(&state)));
+    }
+
+    #[test]
+    fn tampered_headers_are_rejected() {
+        let bytes = key(&GameState::new()).to_bytes();
+
+        let mut version = bytes.clone();
+        version[MAGIC.len()] = 7;
+        assert_eq!(
+            PositionKey::from_bytes(&version),
+            Err(DecodeError::UnsupportedVersion(7))
+        );
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert_eq!(PositionKey::from_bytes(&magic), Err(DecodeError::BadMagic));
+
+        assert_eq!(
+            PositionKey::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::Length {
+                expected: bytes.len(),
+                found: bytes.len() - 1
+            })
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    fs, io,
+    path::Path,
+};
+
+use crate::{Engine, GameMove, GameState, NotationError, Player, PositionError};
+
+/// What the solver has to achieve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PuzzleGoal {
+    /// Play this exact move.
+    BestMove(GameMove),
+    /// Force a win within this many of the solver's own moves, against any defense.
+    WinIn(u32),
+}
+
+#[derive(Debug)]
+pub enum PuzzleError {
+    Io(io::Error),
+    /// A line that is not `key: value`, or an unknown key.
+    InvalidLine(String),
+    MissingField(&'static str),
+    Position(PositionError),
+    Notation(NotationError),
+    InvalidTurn(String),
+    InvalidGoal(String),
+    /// The `best` move can't be played in the puzzle position.
+    IllegalBest(GameMove),
+}
+
+impl Display for PuzzleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PuzzleError::Io(error) => write!(f, "could not read puzzle: {error}"),
+            PuzzleError::InvalidLine(line) => write!(f, "invalid puzzle line {line:?}"),
+            PuzzleError::MissingField(field) => write!(f, "puzzle is missing `{field}`"),
+            PuzzleError::Position(error) => write!(f, "invalid puzzle position: {error}"),
+            PuzzleError::Notation(error) => write!(f, "invalid puzzle move: {error}"),
+            PuzzleError::InvalidTurn(turn) => write!(f, "invalid side to move {turn:?}"),
+            PuzzleError::InvalidGoal(goal) => write!(f, "invalid puzzle goal {goal:?}"),
+            PuzzleError::IllegalBest(best) => write!(f, "puzzle's best move {best:?} is illegal"),
+        }
+    }
+}
+
+impl Error for PuzzleError {}
+
+impl From<io::Error> for PuzzleError {
+    fn from(error: io::Error) -> Self {
+        PuzzleError::Io(error)
+    }
+}
+
+impl From<PositionError> for PuzzleError {
+    fn from(error: PositionError) -> Self {
+        PuzzleError::Position(error)
+    }
+}
+
+impl From<NotationError> for PuzzleError {
+    fn from(error: NotationError) -> Self {
+        PuzzleError::Notation(error)
+    }
+}
+
+/// A position together with the goal the side to move must reach.
+///
+/// Puzzle files are made of `key: value` lines; blank lines and lines starting
+/// with `#` are ignored:
+///
+/// ```text
+/// position: W2,W2,W2,B2/.,.,.,./.,.,.,./.,.,.,.
+/// turn: white
+/// best: 3@d1
+/// ```
+///
+/// Instead of `best`, a puzzle may give `win: N` to require a forced win within
+/// N moves of the side to move.
+pub struct Puzzle {
+    pub state: GameState,
+    pub goal: PuzzleGoal,
+}
+
+impl Puzzle {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Puzzle, PuzzleError> {
+        Puzzle::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Puzzle, PuzzleError> {
+        let mut position = None;
+        let mut turn = Player::White;
+        let mut goal = None;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                return Err(PuzzleError::InvalidLine(line.to_string()));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "position" => position = Some(value),
+                "turn" => {
+                    turn = match value.to_ascii_lowercase().as_str() {
+                        "white" | "w" => Player::White,
+                        "black" | "b" => Player::Black,
+                        _ => return Err(PuzzleError::InvalidTurn(value.to_string())),
+                    }
+                }
+                "best" => goal = Some(PuzzleGoal::BestMove(value.parse()?)),
+                "win" => match value.parse() {
+                    Ok(moves) if moves > 0 => goal = Some(PuzzleGoal::WinIn(moves)),
+                    _ => return Err(PuzzleError::InvalidGoal(value.to_string())),
+                },
+                _ => return Err(PuzzleError::InvalidLine(line.to_string())),
+            }
+        }
+        let position = position.ok_or(PuzzleError::MissingField("position"))?;
+        let state = GameState::from_position(position, turn)?;
+        let goal = goal.ok_or(PuzzleError::MissingField("best` or `win"))?;
+        if let PuzzleGoal::BestMove(best) = goal {
+            if !state.legal_moves().contains(&best) {
+                return Err(PuzzleError::IllegalBest(best));
+            }
+        }
+        Ok(Puzzle { state, goal })
+    }
+
+    /// Run `engine` on the puzzle and check whether it meets the goal.
+    pub fn verify(&self, engine: &mut dyn Engine) -> bool {
+        match self.goal {
+            PuzzleGoal::BestMove(best) => engine.choose_move(&self.state) == Some(best),
+            PuzzleGoal::WinIn(moves) => forces_win(engine, &self.state, moves),
+        }
+    }
+}
+
+/// Whether `engine`, moving for the side to move in `state`, wins within `moves`
+/// of its own moves whatever the opponent replies.
+fn forces_win(engine: &mut dyn Engine, state: &GameState, moves: u32) -> bool {
+    let solver = state.turn;
+    let Some(game_move) = engine.choose_move(state) else {
+        return false;
+    };
+    let mut after_move = state.clone();
+    after_move.apply_move(game_move);
+    if after_move.raw_score().is_win_for(solver) {
+        return true;
+    }
+    if moves <= 1 {
+        return false;
+    }
+    let replies = after_move.branch();
+    // Without a reply the game is over, and only counts if the solver has won.
+    if replies.is_empty() {
+        return after_move.raw_score().is_win_for(solver);
+    }
+    replies.into_iter().all(|(_, reply)| {
+        !reply.raw_score().is_win_for(solver.other()) && forces_win(engine, &reply, moves - 1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MinimaxEngine;
+
+    const BEST_MOVE: &str = "
+        # White completes the top row by covering Black's piece.
+        position: W2,W2,W2,B2/.,.,.,./.,.,.,./.,.,.,.
+        turn: white
+        best: 3@d1
+    ";
+
+    #[test]
+    fn minimax_solves_best_move_puzzle() {
+        let puzzle = Puzzle::parse(BEST_MOVE).unwrap();
+        assert_eq!(puzzle.goal, PuzzleGoal::BestMove("3@d1".parse().unwrap()));
+        assert!(puzzle.verify(&mut MinimaxEngine::new(2)));
+    }
+
+    #[test]
+    fn minimax_solves_win_puzzle() {
+        let puzzle = Puzzle::parse(&BEST_MOVE.replace("best: 3@d1", "win: 1")).unwrap();
+        assert!(puzzle.verify(&mut MinimaxEngine::new(2)));
+    }
+
+    #[test]
+    fn rejects_unplayable_best_move() {
+        // White has no size 2 pieces left in reserve.
+        let text = BEST_MOVE.replace("best: 3@d1", "best: 2@a2");
+        assert!(matches!(
+            Puzzle::parse(&text),
+            Err(PuzzleError::IllegalBest(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_goal() {
+        let text = BEST_MOVE.replace("best: 3@d1", "");
+        assert!(matches!(
+            Puzzle::parse(&text),
+            Err(PuzzleError::MissingField(_))
+        ));
+    }
+}
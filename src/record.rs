@@ -0,0 +1,453 @@
+//! Recorded games and post-game analysis.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    fs, io,
+    path::Path,
+    slice,
+    str::FromStr,
+};
+
+use crate::{
+    EngineError, GameMove, GameState, MinimaxEngine, MoveError, NotationError, Player, Score,
+};
+
+/// How far a move's evaluation may fall short of the best move's, in balanced
+/// score points, before `analyze_game` flags it as a blunder.
+pub const BLUNDER_THRESHOLD: i32 = 4;
+
+/// The moves of a game played from the standard starting position.
+///
+/// Written as the moves' notation separated by spaces, e.g. `3@a1 3@b2 a1-c3`. A game
+/// Black started is prefixed with `black:`, e.g. `black: 3@a1 3@b2`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameRecord {
+    /// Who made the first move.
+    pub first: Player,
+    pub moves: Vec<GameMove>,
+}
+
+impl GameRecord {
+    /// A game White started.
+    pub fn new(moves: Vec<GameMove>) -> GameRecord {
+        GameRecord {
+            first: Player::White,
+            moves,
+        }
+    }
+
+    pub fn with_first(self, first: Player) -> GameRecord {
+        GameRecord { first, ..self }
+    }
+
+    /// The final position, as `GameState::from_move_list` gives it.
+    pub fn replay(&self) -> Result<GameState, (usize, MoveError)> {
+        GameState::from_move_list(&self.moves, self.first)
+    }
+}
+
+impl Default for GameRecord {
+    fn default() -> Self {
+        GameRecord::new(Vec::new())
+    }
+}
+
+impl Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.first == Player::Black {
+            write!(f, "black:")?;
+            if !self.moves.is_empty() {
+                write!(f, " ")?;
+            }
+        }
+        for (index, game_move) in self.moves.iter().enumerate() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{game_move}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for GameRecord {
+    type Err = NotationError;
+
+    fn from_str(text: &str) -> Result<GameRecord, NotationError> {
+        let mut tokens = text.split_whitespace().peekable();
+        let first = match tokens
+            .peek()
+            .map(|token| token.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("white:") => Some(Player::White),
+            Some("black:") => Some(Player::Black),
+            _ => None,
+        };
+        if first.is_some() {
+            tokens.next();
+        }
+        tokens
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(|moves| GameRecord::new(moves).with_first(first.unwrap_or(Player::White)))
+    }
+}
+
+/// What `GameCollection::load` does with a game it can't read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Fail the whole load.
+    Strict,
+    /// Leave the game out, returning its error alongside the games read, and carry
+    /// on with the next game.
+    #[default]
+    SkipInvalid,
+}
+
+#[derive(Debug)]
+pub enum CollectionError {
+    Io(io::Error),
+    /// A line, numbered from 1, that isn't a list of moves in notation.
+    Notation {
+        line: usize,
+        error: NotationError,
+    },
+    /// A game whose move at `index` is illegal.
+    Illegal {
+        line: usize,
+        index: usize,
+        error: MoveError,
+    },
+}
+
+impl Display for CollectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollectionError::Io(error) => write!(f, "could not read games: {error}"),
+            CollectionError::Notation { line, error } => write!(f, "line {line}: {error}"),
+            CollectionError::Illegal { line, index, error } => {
+                write!(f, "line {line}: move {} is illegal: {error}", index + 1)
+            }
+        }
+    }
+}
+
+impl Error for CollectionError {}
+
+impl From<io::Error> for CollectionError {
+    fn from(error: io::Error) -> Self {
+        CollectionError::Io(error)
+    }
+}
+
+/// Many games in one file, one `GameRecord` per line. Blank lines and lines
+/// starting with `#` are ignored, so a game without moves is not kept.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GameCollection {
+    pub games: Vec<GameRecord>,
+}
+
+impl GameCollection {
+    pub fn new(games: Vec<GameRecord>) -> GameCollection {
+        GameCollection { games }
+    }
+
+    pub fn load(
+        path: impl AsRef<Path>,
+        strictness: Strictness,
+    ) -> Result<(GameCollection, Vec<CollectionError>), CollectionError> {
+        GameCollection::parse(&fs::read_to_string(path)?, strictness)
+    }
+
+    /// Read games from `text`, checking that every game can be replayed. Returns the
+    /// games read and the errors of the games skipped under `Strictness::SkipInvalid`.
+    pub fn parse(
+        text: &str,
+        strictness: Strictness,
+    ) -> Result<(GameCollection, Vec<CollectionError>), CollectionError> {
+        let mut games = Vec::new();
+        let mut skipped = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_text = line.trim();
+            if line_text.is_empty() || line_text.starts_with('#') {
+                continue;
+            }
+            let line = index + 1;
+            let game = line_text
+                .parse::<GameRecord>()
+                .map_err(|error| CollectionError::Notation { line, error })
+                .and_then(|game| match game.replay() {
+                    Ok(_) => Ok(game),
+                    Err((index, error)) => Err(CollectionError::Illegal { line, index, error }),
+                });
+            match game {
+                Ok(game) => games.push(game),
+                Err(error) if strictness == Strictness::Strict => return Err(error),
+                Err(error) => skipped.push(error),
+            }
+        }
+        Ok((GameCollection { games }, skipped))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, GameRecord> {
+        self.games.iter()
+    }
+}
+
+impl Display for GameCollection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for game in &self.games {
+            writeln!(f, "{game}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a GameCollection {
+    type Item = &'a GameRecord;
+    type IntoIter = slice::Iter<'a, GameRecord>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// The engine's view of one move of a recorded game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MoveAnnotation {
+    /// Index of the move in the record.
+    pub ply: usize,
+    pub played: GameMove,
+    /// The move the engine would have played.
+    pub best_move: GameMove,
+    /// The position's value before the move, assuming the engine's move is played.
+    pub eval_before: Score,
+    /// The position's value after the move actually played.
+    pub eval_played: Score,
+    /// The played move is clearly worse for the mover than the engine's move.
+    pub blunder: bool,
+}
+
+/// How a move compares with the best move available, by how many balanced score
+/// points it gives up for the mover. Giving up a forced win, or walking into a
+/// forced loss, is always a blunder; delaying a forced win, or hastening a forced
+/// loss, is only good.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MoveGrade {
+    /// As good as the best move.
+    Best,
+    /// Gives up at most `GOOD_THRESHOLD` points.
+    Good,
+    /// Gives up at most `INACCURACY_THRESHOLD` points.
+    Inaccuracy,
+    /// Gives up less than `BLUNDER_THRESHOLD` points.
+    Mistake,
+    Blunder,
+}
+
+/// The most points a `MoveGrade::Good` move may give up.
+pub const GOOD_THRESHOLD: i32 = 1;
+/// The most points a `MoveGrade::Inaccuracy` may give up.
+pub const INACCURACY_THRESHOLD: i32 = 2;
+
+impl MoveGrade {
+    /// The grade of a move scoring `played` for `mover`, when the best move scores
+    /// `best`.
+    pub fn of(mover: Player, best: Score, played: Score) -> MoveGrade {
+        let worse = if mover == Player::White {
+            played < best
+        } else {
+            played > best
+        };
+        let drop = match (best, played) {
+            _ if !worse => return MoveGrade::Best,
+            (Score::Balanced(best), Score::Balanced(played)) => best.abs_diff(played),
+            // The same forced result, only further off.
+            _ if best.winner() == played.winner() => return MoveGrade::Good,
+            _ => return MoveGrade::Blunder,
+        };
+        if drop <= GOOD_THRESHOLD as u32 {
+            MoveGrade::Good
+        } else if drop <= INACCURACY_THRESHOLD as u32 {
+            MoveGrade::Inaccuracy
+        } else if drop < BLUNDER_THRESHOLD as u32 {
+            MoveGrade::Mistake
+        } else {
+            MoveGrade::Blunder
+        }
+    }
+}
+
+/// Why `analyze_game` could not annotate a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalysisError {
+    /// The move at this index of the record is illegal.
+    Illegal(usize, MoveError),
+    Engine(EngineError),
+}
+
+impl Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalysisError::Illegal(index, error) => {
+                write!(f, "move {} is illegal: {error}", index + 1)
+            }
+            AnalysisError::Engine(error) => write!(f, "search failed: {error}"),
+        }
+    }
+}
+
+impl Error for AnalysisError {}
+
+impl From<EngineError> for AnalysisError {
+    fn from(error: EngineError) -> Self {
+        AnalysisError::Engine(error)
+    }
+}
+
+/// Replay `record`, searching every position with `engine` to its configured depth,
+/// and annotate each move. Fails like `GameRecord::replay` on an illegal move, or
+/// if the engine's configuration can't be searched with.
+pub fn analyze_game(
+    record: &GameRecord,
+    engine: &mut MinimaxEngine,
+) -> Result<Vec<MoveAnnotation>, AnalysisError> {
+    let depth = engine.config().depth.max(1);
+    let mut state = GameState::new_with_turn(record.first);
+    let mut annotations = Vec::with_capacity(record.moves.len());
+    for (ply, &played) in record.moves.iter().enumerate() {
+        state
+            .check_move(played)
+            .map_err(|error| AnalysisError::Illegal(ply, error))?;
+        // The move is legal, so the search only finds no move in a dead draw, where
+        // the played move is as good as any.
+        let result = engine.search_root(&state, depth)?;
+        let (best_move, eval_before) = (result.best_move.unwrap_or(played), result.score);
+        let eval_played = engine.score_move(&state, played, depth);
+        annotations.push(MoveAnnotation {
+            ply,
+            played,
+            best_move,
+            eval_before,
+            eval_played,
+            blunder: MoveGrade::of(state.turn(), eval_before, eval_played) == MoveGrade::Blunder,
+        });
+        state.make_move(played);
+    }
+    Ok(annotations)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn record(text: &str) -> GameRecord {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn slower_wins_are_good_and_lost_wins_are_blunders() {
+        let white = Player::White;
+        assert_eq!(
+            MoveGrade::of(white, Score::WhiteFavored(1), Score::WhiteFavored(3)),
+            MoveGrade::Good
+        );
+        assert_eq!(
+            MoveGrade::of(white, Score::WhiteFavored(1), Score::Balanced(0)),
+            MoveGrade::Blunder
+        );
+        assert_eq!(
+            MoveGrade::of(white, Score::BlackFavored(4), Score::BlackFavored(2)),
+            MoveGrade::Good
+        );
+        assert_eq!(
+            MoveGrade::of(
+                Player::Black,
+                Score::BlackFavored(2),
+                Score::BlackFavored(2)
+            ),
+            MoveGrade::Best
+        );
+    }
+
+    #[test]
+    fn saved_collections_reload_and_replay() {
+        let collection = GameCollection::new(vec![
+            record("3@a1 3@d4 2@b2"),
+            record("3@b2 3@c3 b2-a1 2@b2"),
+            record("0@d1"),
+            record("black: 3@a1 3@d4"),
+        ]);
+        let path = env::temp_dir().join(format!("goblet-collection-{}.txt", std::process::id()));
+        collection.save(&path).unwrap();
+        let loaded = GameCollection::load(&path, Strictness::Strict);
+        fs::remove_file(&path).unwrap();
+        let (loaded, skipped) = loaded.unwrap();
+        assert_eq!(loaded, collection);
+        assert!(skipped.is_empty());
+        for game in &loaded {
+            assert!(game.replay().is_ok());
+        }
+    }
+
+    #[test]
+    fn games_black_started_replay_with_black_first() {
+        let game = record("black: 3@a1 3@d4 2@b2");
+        assert_eq!(game.first, Player::Black);
+        assert_eq!(game.to_string().parse::<GameRecord>().unwrap(), game);
+        let expected =
+            GameState::from_position("B3,.,.,./.,B2,.,./.,.,.,./.,.,.,W3", Player::White).unwrap();
+        assert_eq!(
+            game.replay().unwrap().position_string(),
+            expected.position_string()
+        );
+        assert_eq!(record("3@a1").first, Player::White);
+    }
+
+    #[test]
+    fn invalid_games_are_skipped_and_reported() {
+        let text = "3@a1 3@d4\n# a comment\n3@a1 3@a1\nnot a move\n2@b2\n";
+        let (collection, skipped) = GameCollection::parse(text, Strictness::SkipInvalid).unwrap();
+        assert_eq!(collection.games, vec![record("3@a1 3@d4"), record("2@b2")]);
+        assert!(matches!(
+            skipped[..],
+            [
+                CollectionError::Illegal {
+                    line: 3,
+                    index: 1,
+                    ..
+                },
+                CollectionError::Notation { line: 4, .. },
+            ]
+        ));
+        assert!(matches!(
+            GameCollection::parse(text, Strictness::Strict),
+            Err(CollectionError::Illegal { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn annotations_follow_the_moves_and_flag_a_missed_block() {
+        // Black's 0@c4 leaves a4 open for White's fourth piece in the a file.
+        let game = record("3@a1 0@d4 3@a2 0@d3 3@a3 0@c4 2@a4");
+        let annotations = analyze_game(&game, &mut MinimaxEngine::new(2)).unwrap();
+        assert_eq!(annotations.len(), game.moves.len());
+        for (ply, annotation) in annotations.iter().enumerate() {
+            assert_eq!(annotation.ply, ply);
+            assert_eq!(annotation.played, game.moves[ply]);
+        }
+        let missed_block = &annotations[5];
+        assert!(missed_block.blunder);
+        assert_eq!(missed_block.best_move.dest(), Some((3, 0)));
+        assert_eq!(missed_block.eval_played, Score::WhiteFavored(2));
+        assert!(!annotations[6].blunder);
+    }
+}
@@ -0,0 +1,485 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    fs, io, mem,
+    path::Path,
+};
+
+use crate::{
+    zobrist::{self, ZobristKeys},
+    GameMove, GameState, Score, BOARD_COLS, BOARD_ROWS, NUM_SIZES,
+};
+
+/// The tag that starts every table written by `TranspositionTable::save`.
+pub const MAGIC: [u8; 4] = *b"GBTT";
+/// The format version `TranspositionTable::save` writes. Bump it whenever the
+/// layout changes.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Magic, version, seed, policy and slot count.
+const HEADER_LEN: usize = MAGIC.len() + 1 + 8 + 1 + 8;
+/// Index, key, depth, bound, score and best move.
+const ENTRY_LEN: usize = 8 + 8 + 4 + 1 + 5 + 5;
+
+/// Decides which entry survives when two positions map to the same slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplacementPolicy {
+    /// Keep the entry searched to the greater depth.
+    #[default]
+    DepthPreferred,
+    /// Newest entry always wins.
+    AlwaysReplace,
+    /// Each slot holds a depth-preferred entry and an always-replace entry,
+    /// trading twice the memory for a better hit rate.
+    TwoBucket,
+}
+
+impl ReplacementPolicy {
+    fn to_byte(self) -> u8 {
+        match self {
+            ReplacementPolicy::DepthPreferred => 0,
+            ReplacementPolicy::AlwaysReplace => 1,
+            ReplacementPolicy::TwoBucket => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<ReplacementPolicy> {
+        match byte {
+            0 => Some(ReplacementPolicy::DepthPreferred),
+            1 => Some(ReplacementPolicy::AlwaysReplace),
+            2 => Some(ReplacementPolicy::TwoBucket),
+            _ => None,
+        }
+    }
+
+    /// How many entries each slot holds.
+    fn buckets(self) -> usize {
+        match self {
+            ReplacementPolicy::TwoBucket => 2,
+            ReplacementPolicy::DepthPreferred | ReplacementPolicy::AlwaysReplace => 1,
+        }
+    }
+
+    /// The memory each slot of a table with this policy takes.
+    pub fn slot_bytes(self) -> usize {
+        self.buckets() * mem::size_of::<Option<TtEntry>>()
+    }
+}
+
+/// How a stored score relates to the true minimax value of the position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    /// The search failed high: the true value is at least the score.
+    Lower,
+    /// The search failed low: the true value is at most the score.
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TtEntry {
+    pub key: u64,
+    pub depth: i32,
+    pub score: Score,
+    pub bound: Bound,
+    pub best_move: Option<GameMove>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file doesn't start with `MAGIC`.
+    BadMagic,
+    /// Written by a version of the format this build doesn't know.
+    UnsupportedVersion(u8),
+    /// The table was keyed with Zobrist keys from another seed, so its keys are
+    /// meaningless here.
+    SeedMismatch {
+        expected: u64,
+        found: u64,
+    },
+    /// The file ends early or runs on past the last entry.
+    Length {
+        expected: usize,
+        found: usize,
+    },
+    /// A field holds a value no table could have written.
+    Corrupt(&'static str),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "could not read transposition table: {error}"),
+            LoadError::BadMagic => write!(f, "not a saved transposition table"),
+            LoadError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "unsupported transposition table format version {version}"
+                )
+            }
+            LoadError::SeedMismatch { expected, found } => write!(
+                f,
+                "table was keyed with Zobrist seed {found:#x}, expected {expected:#x}"
+            ),
+            LoadError::Length { expected, found } => {
+                write!(f, "expected {expected} bytes but found {found}")
+            }
+            LoadError::Corrupt(field) => write!(f, "invalid {field} in transposition table"),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+pub struct TranspositionTable {
+    /// `policy.buckets()` consecutive entries per slot.
+    entries: Vec<Option<TtEntry>>,
+    policy: ReplacementPolicy,
+    seed: u64,
+    /// Keys for a seed other than `zobrist::DEFAULT_SEED`; see `with_seed`.
+    keys: Option<Box<ZobristKeys>>,
+}
+
+impl TranspositionTable {
+    /// A table with room for `size` slots (at least one).
+    pub fn new(size: usize, policy: ReplacementPolicy) -> TranspositionTable {
+        TranspositionTable {
+            entries: vec![None; size.max(1) * policy.buckets()],
+            policy,
+            seed: zobrist::DEFAULT_SEED,
+            keys: None,
+        }
+    }
+
+    /// Key positions with Zobrist keys generated from `seed` rather than the
+    /// default ones. Hashing with other keys recomputes the hash at every probe, so
+    /// searches are slower.
+    pub fn with_seed(self, seed: u64) -> TranspositionTable {
+        TranspositionTable {
+            keys: (seed != zobrist::DEFAULT_SEED).then(|| Box::new(ZobristKeys::from_seed(seed))),
+            seed,
+            ..self
+        }
+    }
+
+    /// The seed of the Zobrist keys the table is keyed with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The key `state` is stored and probed under.
+    pub fn key(&self, state: &GameState) -> u64 {
+        match &self.keys {
+            Some(keys) => state.zobrist_with(keys),
+            None => state.zobrist(),
+        }
+    }
+
+    /// The memory the slots take, the table's bulk.
+    pub fn memory_bytes(&self) -> usize {
+        self.slot_count() * self.policy.slot_bytes()
+    }
+
+    fn slot_count(&self) -> usize {
+        self.entries.len() / self.policy.buckets()
+    }
+
+    fn slot(&self, key: u64) -> usize {
+        (key % self.slot_count() as u64) as usize
+    }
+
+    fn slot_entries(&self, slot: usize) -> &[Option<TtEntry>] {
+        let buckets = self.policy.buckets();
+        &self.entries[slot * buckets..(slot + 1) * buckets]
+    }
+
+    fn slot_entries_mut(&mut self, slot: usize) -> &mut [Option<TtEntry>] {
+        let buckets = self.policy.buckets();
+        &mut self.entries[slot * buckets..(slot + 1) * buckets]
+    }
+
+    pub fn probe(&self, key: u64) -> Option<&TtEntry> {
+        self.slot_entries(self.slot(key))
+            .iter()
+            .flatten()
+            .find(|entry| entry.key == key)
+    }
+
+    pub fn store(&mut self, entry: TtEntry) {
+        let policy = self.policy;
+        let slot = self.slot(entry.key);
+        let (primary, secondary) = match self.slot_entries_mut(slot) {
+            [primary, rest @ ..] => (primary, rest.first_mut()),
+            [] => unreachable!("every slot has at least one bucket"),
+        };
+        let replaces_primary = match primary {
+            None => true,
+            Some(existing) => existing.key == entry.key || entry.depth >= existing.depth,
+        };
+        match policy {
+            ReplacementPolicy::AlwaysReplace => *primary = Some(entry),
+            ReplacementPolicy::DepthPreferred => {
+                if replaces_primary {
+                    *primary = Some(entry);
+                }
+            }
+            ReplacementPolicy::TwoBucket => {
+                let secondary = secondary.expect("two-bucket slots have a secondary entry");
+                if replaces_primary {
+                    *primary = Some(entry);
+                    if secondary.is_some_and(|existing| existing.key == entry.key) {
+                        *secondary = None;
+                    }
+                } else {
+                    *secondary = Some(entry);
+                }
+            }
+        }
+    }
+
+    /// Write the table to `path` as `MAGIC`, `FORMAT_VERSION`, the Zobrist seed, the
+    /// policy and the slot count, then each stored entry with its index. Integers
+    /// are little-endian.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let stored = self.entries.iter().filter(|entry| entry.is_some()).count();
+        let mut bytes = Vec::with_capacity(HEADER_LEN + stored * ENTRY_LEN);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.push(self.policy.to_byte());
+        bytes.extend_from_slice(&(self.slot_count() as u64).to_le_bytes());
+        for (index, entry) in self.entries.iter().enumerate() {
+            if let Some(entry) = entry {
+                bytes.extend_from_slice(&(index as u64).to_le_bytes());
+                encode_entry(entry, &mut bytes);
+            }
+        }
+        fs::write(path, bytes)
+    }
+
+    /// Read a table written by `save`. Fails with `LoadError::SeedMismatch` unless
+    /// the table was keyed with the Zobrist keys of `seed`.
+    pub fn load(path: impl AsRef<Path>, seed: u64) -> Result<TranspositionTable, LoadError> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < HEADER_LEN {
+            return Err(LoadError::Length {
+                expected: HEADER_LEN,
+                found: bytes.len(),
+            });
+        }
+        let (header, body) = bytes.split_at(HEADER_LEN);
+        if header[..MAGIC.len()] != MAGIC {
+            return Err(LoadError::BadMagic);
+        }
+        let header = &header[MAGIC.len()..];
+        if header[0] != FORMAT_VERSION {
+            return Err(LoadError::UnsupportedVersion(header[0]));
+        }
+        let found = u64::from_le_bytes(header[1..9].try_into().unwrap());
+        if found != seed {
+            return Err(LoadError::SeedMismatch {
+                expected: seed,
+                found,
+            });
+        }
+        let policy = ReplacementPolicy::from_byte(header[9]).ok_or(LoadError::Corrupt("policy"))?;
+        let slots = u64::from_le_bytes(header[10..18].try_into().unwrap());
+        // Every slot is allocated up front, so don't trust a count no real table has.
+        let slots = usize::try_from(slots)
+            .ok()
+            .filter(|&slots| slots > 0 && slots <= isize::MAX as usize / policy.slot_bytes())
+            .ok_or(LoadError::Corrupt("slot count"))?;
+        if body.len() % ENTRY_LEN != 0 {
+            return Err(LoadError::Length {
+                expected: HEADER_LEN + body.len() / ENTRY_LEN * ENTRY_LEN,
+                found: bytes.len(),
+            });
+        }
+
+        let mut table = TranspositionTable::new(slots, policy).with_seed(seed);
+        for chunk in body.chunks_exact(ENTRY_LEN) {
+            let (index, entry) = chunk.split_at(8);
+            let index = usize::try_from(u64::from_le_bytes(index.try_into().unwrap()))
+                .ok()
+                .filter(|&index| index < table.entries.len())
+                .ok_or(LoadError::Corrupt("entry index"))?;
+            table.entries[index] = Some(decode_entry(entry)?);
+        }
+        Ok(table)
+    }
+}
+
+fn encode_entry(entry: &TtEntry, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&entry.key.to_le_bytes());
+    bytes.extend_from_slice(&entry.depth.to_le_bytes());
+    bytes.push(match entry.bound {
+        Bound::Exact => 0,
+        Bound::Lower => 1,
+        Bound::Upper => 2,
+    });
+    let (tag, value) = match entry.score {
+        Score::WhiteFavored(plies) => (0, plies.to_le_bytes()),
+        Score::BlackFavored(plies) => (1, plies.to_le_bytes()),
+        Score::Balanced(value) => (2, value.to_le_bytes()),
+    };
+    bytes.push(tag);
+    bytes.extend_from_slice(&value);
+    // Coordinates and sizes are tiny, so a byte each is plenty.
+    let (tag, fields) = match entry.best_move {
+        None => (0, [0; 4]),
+        Some(GameMove::Place { size, dest }) => (1, [size as u8, dest.0 as u8, dest.1 as u8, 0]),
+        Some(GameMove::Move { source, dest }) => (
+            2,
+            [source.0 as u8, source.1 as u8, dest.0 as u8, dest.1 as u8],
+        ),
+        Some(GameMove::Pass) => (3, [0; 4]),
+    };
+    bytes.push(tag);
+    bytes.extend_from_slice(&fields);
+}
+
+fn decode_entry(bytes: &[u8]) -> Result<TtEntry, LoadError> {
+    let key = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+    let depth = i32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    let bound = match bytes[12] {
+        0 => Bound::Exact,
+        1 => Bound::Lower,
+        2 => Bound::Upper,
+        _ => return Err(LoadError::Corrupt("bound")),
+    };
+    let value: [u8; 4] = bytes[14..18].try_into().unwrap();
+    let score = match bytes[13] {
+        0 => Score::WhiteFavored(u32::from_le_bytes(value)),
+        1 => Score::BlackFavored(u32::from_le_bytes(value)),
+        2 => Score::Balanced(i32::from_le_bytes(value)),
+        _ => return Err(LoadError::Corrupt("score")),
+    };
+    let [a, b, c, d] = bytes[19..23].try_into().unwrap();
+    let [a, b, c, d] = [a, b, c, d].map(usize::from);
+    let on_board = |(row, col): (usize, usize)| row < BOARD_ROWS && col < BOARD_COLS;
+    let best_move = match bytes[18] {
+        0 => None,
+        1 if a < NUM_SIZES && on_board((b, c)) => Some(GameMove::Place {
+            size: a,
+            dest: (b, c),
+        }),
+        2 if on_board((a, b)) && on_board((c, d)) => Some(GameMove::Move {
+            source: (a, b),
+            dest: (c, d),
+        }),
+        3 => Some(GameMove::Pass),
+        _ => return Err(LoadError::Corrupt("best move")),
+    };
+    Ok(TtEntry {
+        key,
+        depth,
+        score,
+        bound,
+        best_move,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::{alpha_beta, EngineConfig, GameState, MinimaxEngine};
+
+    const POLICIES: [ReplacementPolicy; 3] = [
+        ReplacementPolicy::DepthPreferred,
+        ReplacementPolicy::AlwaysReplace,
+        ReplacementPolicy::TwoBucket,
+    ];
+
+    fn midgame() -> GameState {
+        let mut state = GameState::new();
+        for notation in ["2@a1", "3@d4", "1@b2", "2@c3", "3@b1"] {
+            state.apply_move(notation.parse().unwrap());
+        }
+        state
+    }
+
+    #[test]
+    fn every_policy_matches_search_without_table() {
+        let state = midgame();
+        let config = EngineConfig::default().with_depth(3);
+        let expected = MinimaxEngine::from_config(config).search(&state).unwrap();
+        for policy in POLICIES {
+            // A small table, so entries collide and get replaced.
+            let mut engine =
+                MinimaxEngine::from_config(config.with_transposition_table(64, policy));
+            let result = engine.search(&state).unwrap();
+            assert_eq!(result.score, expected.score, "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn only_two_bucket_tables_pay_for_a_second_entry() {
+        let size = |policy| TranspositionTable::new(100, policy).memory_bytes();
+        assert_eq!(
+            size(ReplacementPolicy::DepthPreferred),
+            size(ReplacementPolicy::AlwaysReplace)
+        );
+        assert_eq!(
+            size(ReplacementPolicy::TwoBucket),
+            2 * size(ReplacementPolicy::DepthPreferred)
+        );
+    }
+
+    #[test]
+    fn stored_entries_can_be_probed() {
+        for policy in POLICIES {
+            let mut table = TranspositionTable::new(16, policy);
+            let entry = TtEntry {
+                key: 42,
+                depth: 3,
+                score: Score::Balanced(7),
+                best_move: None,
+                bound: Bound::Exact,
+            };
+            table.store(entry);
+            assert_eq!(table.probe(42).map(|entry| entry.score), Some(entry.score));
+            assert!(table.probe(43).is_none());
+        }
+    }
+
+    #[test]
+    fn saved_tables_reload_with_their_best_moves() {
+        let mut state = midgame();
+        let seed = 0x5eed;
+        let mut table =
+            TranspositionTable::new(1 << 12, ReplacementPolicy::TwoBucket).with_seed(seed);
+        alpha_beta(&mut state, 3, Score::MIN, Score::MAX, Some(&mut table));
+        let key = table.key(&state);
+        let cached = *table.probe(key).unwrap();
+        assert!(cached.best_move.is_some());
+
+        let path = env::temp_dir().join(format!("goblet-table-{}.bin", std::process::id()));
+        table.save(&path).unwrap();
+        let loaded = TranspositionTable::load(&path, seed);
+        let stale = TranspositionTable::load(&path, zobrist::DEFAULT_SEED);
+        fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.seed(), seed);
+        assert_eq!(loaded.memory_bytes(), table.memory_bytes());
+        let reloaded = loaded.probe(loaded.key(&state)).unwrap();
+        assert_eq!(reloaded.best_move, cached.best_move);
+        assert_eq!(reloaded.score, cached.score);
+        assert!(matches!(
+            stale,
+            Err(LoadError::SeedMismatch {
+                expected: zobrist::DEFAULT_SEED,
+                found: 0x5eed,
+            })
+        ));
+    }
+}
@@ -0,0 +1,145 @@
+//! Fixed random keys for Zobrist hashing. The keys are generated at compile time
+//! from `DEFAULT_SEED`, so hashes are stable across runs and can be stored on disk.
+//! Other seeds give independent keys, e.g. to rule out a systematic collision.
+
+use crate::{BOARD_COLS, BOARD_ROWS, NUM_SIZES};
+
+/// The seed of the keys `GameState::zobrist` uses. It and the way keys are
+/// generated from it only change in a new major release, so hashes written to
+/// disk stay valid until then.
+pub const DEFAULT_SEED: u64 = 0x676f_626c_6574_2121;
+
+pub struct ZobristKeys {
+    /// Indexed by row, column, size and color (0 for White, 1 for Black).
+    pub pieces: [[[[u64; 2]; NUM_SIZES]; BOARD_COLS]; BOARD_ROWS],
+    /// Indexed by color and size; rotated by the reserve count before mixing in.
+    pub reserves: [[u64; NUM_SIZES]; 2],
+    /// Mixed in when Black is to move.
+    pub black_to_move: u64,
+    /// Mixed in when the last move was a pass, so the side to move can't pass.
+    pub passed: u64,
+    /// Indexed by row and column. Under `RuleSet::forbid_reversal`, the keys of the
+    /// source and destination of the relocation the side to move may not reverse.
+    pub moved_from: [[u64; BOARD_COLS]; BOARD_ROWS],
+    pub moved_to: [[u64; BOARD_COLS]; BOARD_ROWS],
+}
+
+/// One step of the SplitMix64 generator.
+const fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl ZobristKeys {
+    /// The keys generated from `seed`. The same seed always gives the same keys.
+    pub const fn from_seed(seed: u64) -> ZobristKeys {
+        generate(seed)
+    }
+}
+
+const fn generate(seed: u64) -> ZobristKeys {
+    let mut state = seed;
+    let mut keys = ZobristKeys {
+        pieces: [[[[0; 2]; NUM_SIZES]; BOARD_COLS]; BOARD_ROWS],
+        reserves: [[0; NUM_SIZES]; 2],
+        black_to_move: 0,
+        passed: 0,
+        moved_from: [[0; BOARD_COLS]; BOARD_ROWS],
+        moved_to: [[0; BOARD_COLS]; BOARD_ROWS],
+    };
+    let mut row = 0;
+    while row < BOARD_ROWS {
+        let mut col = 0;
+        while col < BOARD_COLS {
+            let mut size = 0;
+            while size < NUM_SIZES {
+                keys.pieces[row][col][size][0] = split_mix(&mut state);
+                keys.pieces[row][col][size][1] = split_mix(&mut state);
+                size += 1;
+            }
+            col += 1;
+        }
+        row += 1;
+    }
+    let mut size = 0;
+    while size < NUM_SIZES {
+        keys.reserves[0][size] = split_mix(&mut state);
+        keys.reserves[1][size] = split_mix(&mut state);
+        size += 1;
+    }
+    keys.black_to_move = split_mix(&mut state);
+    // Generated after the others, so that adding them left the older keys as they were.
+    keys.passed = split_mix(&mut state);
+    let mut row = 0;
+    while row < BOARD_ROWS {
+        let mut col = 0;
+        while col < BOARD_COLS {
+            keys.moved_from[row][col] = split_mix(&mut state);
+            keys.moved_to[row][col] = split_mix(&mut state);
+            col += 1;
+        }
+        row += 1;
+    }
+    keys
+}
+
+/// The keys for `DEFAULT_SEED`. A static rather than a constant, so the table
+/// exists once instead of being copied into every function that reads it.
+pub static KEYS: ZobristKeys = generate(DEFAULT_SEED);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GameMove, GameState, Player};
+
+    fn all_keys(keys: &ZobristKeys) -> Vec<u64> {
+        let mut all: Vec<u64> = keys
+            .pieces
+            .iter()
+            .flatten()
+            .flatten()
+            .flatten()
+            .copied()
+            .collect();
+        all.extend(keys.reserves.iter().flatten());
+        all.extend([keys.black_to_move, keys.passed]);
+        all.extend(keys.moved_from.iter().flatten());
+        all.extend(keys.moved_to.iter().flatten());
+        all
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_keys() {
+        assert_eq!(
+            all_keys(&ZobristKeys::from_seed(7)),
+            all_keys(&ZobristKeys::from_seed(7))
+        );
+        assert_eq!(
+            all_keys(&ZobristKeys::from_seed(DEFAULT_SEED)),
+            all_keys(&KEYS)
+        );
+        assert_ne!(
+            all_keys(&ZobristKeys::from_seed(7)),
+            all_keys(&ZobristKeys::from_seed(8))
+        );
+    }
+
+    #[test]
+    fn default_hashes_match_earlier_runs() {
+        // Recorded from an earlier build; a change here invalidates stored hashes.
+        assert_eq!(GameState::new().zobrist(), 0x5bff_5cb3_e827_760c);
+        let moves: Vec<GameMove> = ["3@a1", "0@b2", "2@c3"]
+            .iter()
+            .map(|notation| notation.parse().unwrap())
+            .collect();
+        let state = GameState::from_move_list(&moves, Player::White).unwrap();
+        assert_eq!(state.zobrist(), 0x54c7_01bb_82a2_b8a1);
+        assert_eq!(
+            state.zobrist_with(&ZobristKeys::from_seed(DEFAULT_SEED)),
+            state.zobrist()
+        );
+    }
+}